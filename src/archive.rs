@@ -0,0 +1,311 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Archives
+//!
+//! Container format packing many detached timestamps into a single file.
+//! The file starts with an index listing, for every entry, the document
+//! digest, an optional filename, and the location of the entry's proof.
+//! The proofs follow, each serialized exactly as a standalone `.ots` file,
+//! so a single entry can be extracted by copying its bytes out.
+//!
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use error::Error;
+use ser::{self, DetachedTimestampFile, DigestType};
+
+/// Magic bytes that every archive must start with
+const MAGIC: &[u8] = b"\x00OpenTimestamps\x00\x00Archive\x00\x7c\x3b\x1a\x92\xd0\x45\x6e\x0f";
+
+/// Major version of archives we understand
+const VERSION: usize = 1;
+
+/// Maximum length of a filename stored in the index
+const MAX_NAME_LEN: usize = 4096;
+
+/// A single timestamp stored in an archive
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ArchiveEntry {
+    /// Name of the timestamped file, if known. An empty name is
+    /// stored as no name.
+    pub name: Option<String>,
+    /// The timestamp itself
+    pub file: DetachedTimestampFile
+}
+
+/// An in-memory archive of detached timestamps
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Archive {
+    /// The entries, in file order
+    pub entries: Vec<ArchiveEntry>
+}
+
+impl Archive {
+    /// Constructs a new empty archive
+    pub fn new() -> Archive {
+        Archive { entries: vec![] }
+    }
+
+    /// Adds a timestamp to the end of the archive
+    pub fn push(&mut self, name: Option<String>, file: DetachedTimestampFile) {
+        self.entries.push(ArchiveEntry { name, file });
+    }
+
+    /// Deserialize an entire archive from a reader
+    pub fn from_reader<R: Read>(reader: R) -> Result<Archive, Error> {
+        let mut deser = ser::Deserializer::new(reader);
+        let index = read_index(&mut deser)?;
+        let mut reader = deser.into_inner();
+
+        let mut entries = Vec::with_capacity(index.len());
+        let mut pos = 0;
+        for entry in index {
+            // Entries are written back to back, so anything else
+            // indicates a corrupt index
+            if entry.offset != pos {
+                return Err(Error::IndexMismatch);
+            }
+            let file = DetachedTimestampFile::from_reader(reader.by_ref().take(entry.len as u64))?;
+            if file.digest_type != entry.digest_type || file.timestamp.start_digest != entry.digest {
+                return Err(Error::IndexMismatch);
+            }
+            pos = match pos.checked_add(entry.len) {
+                Some(pos) => pos,
+                None => return Err(Error::IndexMismatch)
+            };
+            entries.push(ArchiveEntry { name: entry.name, file });
+        }
+
+        ser::Deserializer::new(reader).check_eof()?;
+        Ok(Archive { entries })
+    }
+
+    /// Serialize the archive into a writer
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut proofs = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let mut data = vec![];
+            entry.file.to_writer(&mut data)?;
+            proofs.push(data);
+        }
+
+        let mut ser = ser::Serializer::new(writer);
+        ser.write_fixed_bytes(MAGIC)?;
+        ser.write_uint(VERSION)?;
+        ser.write_uint(self.entries.len())?;
+        let mut offset = 0;
+        for (entry, proof) in self.entries.iter().zip(proofs.iter()) {
+            ser.write_byte(entry.file.digest_type.to_tag())?;
+            ser.write_fixed_bytes(&entry.file.timestamp.start_digest)?;
+            match entry.name {
+                Some(ref name) => ser.write_bytes(name.as_bytes())?,
+                None => ser.write_bytes(&[])?
+            }
+            ser.write_uint(offset)?;
+            ser.write_uint(proof.len())?;
+            offset += proof.len();
+        }
+        for proof in &proofs {
+            ser.write_fixed_bytes(proof)?;
+        }
+        Ok(())
+    }
+}
+
+/// Index record describing one entry of an archive
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IndexEntry {
+    /// The claimed hash function used to produce the document digest
+    pub digest_type: DigestType,
+    /// The document digest
+    pub digest: Vec<u8>,
+    /// Name of the timestamped file, if known
+    pub name: Option<String>,
+    /// Offset of the proof, relative to the end of the index
+    pub offset: usize,
+    /// Length of the serialized proof
+    pub len: usize
+}
+
+/// Reads the index at the start of an archive
+fn read_index<R: Read>(deser: &mut ser::Deserializer<R>) -> Result<Vec<IndexEntry>, Error> {
+    let recv_magic = deser.read_fixed_bytes(MAGIC.len())?;
+    if recv_magic != MAGIC {
+        return Err(Error::BadMagic(recv_magic));
+    }
+    let recv_version = deser.read_uint()?;
+    if recv_version != VERSION {
+        return Err(Error::BadVersion(recv_version));
+    }
+
+    let count = deser.read_uint()?;
+    let mut index = vec![];
+    for _ in 0..count {
+        let digest_type = DigestType::from_tag(deser.read_byte()?)?;
+        let digest = deser.read_fixed_bytes(digest_type.digest_len())?;
+        let name = String::from_utf8(deser.read_bytes(0, MAX_NAME_LEN)?)?;
+        index.push(IndexEntry {
+            digest_type,
+            digest,
+            name: if name.is_empty() { None } else { Some(name) },
+            offset: deser.read_uint()?,
+            len: deser.read_uint()?
+        });
+    }
+    Ok(index)
+}
+
+/// Random-access reader over a seekable archive, which parses only
+/// the index up front and individual proofs on demand
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    index: Vec<IndexEntry>,
+    data_start: u64,
+    data_end: u64
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Reads the index of an archive
+    pub fn new(reader: R) -> Result<ArchiveReader<R>, Error> {
+        let mut deser = ser::Deserializer::new(reader);
+        let index = read_index(&mut deser)?;
+        let mut reader = deser.into_inner();
+        let data_start = reader.stream_position()?;
+        let data_end = reader.seek(SeekFrom::End(0))?;
+        Ok(ArchiveReader { reader, index, data_start, data_end })
+    }
+
+    /// The archive's index
+    pub fn index(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    /// Finds the position in the index of the first entry with the given digest
+    pub fn find(&self, digest: &[u8]) -> Option<usize> {
+        self.index.iter().position(|entry| entry.digest == digest)
+    }
+
+    /// Returns the serialized `.ots` bytes of the `n`th entry
+    pub fn extract_raw(&mut self, n: usize) -> Result<Vec<u8>, Error> {
+        let (offset, len) = match self.index.get(n) {
+            Some(entry) => (entry.offset, entry.len),
+            None => return Err(Error::NoSuchEntry(n))
+        };
+        // The index is untrusted, so check the entry lies within the
+        // archive before allocating space for it
+        let start = self.data_start.checked_add(offset as u64);
+        let end = start.and_then(|start| start.checked_add(len as u64));
+        let start = match (start, end) {
+            (Some(start), Some(end)) if end <= self.data_end => start,
+            _ => return Err(Error::Truncated)
+        };
+        self.reader.seek(SeekFrom::Start(start))?;
+        let mut data = Vec::with_capacity(len);
+        self.reader.by_ref().take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(Error::Truncated);
+        }
+        Ok(data)
+    }
+
+    /// Parses the `n`th entry
+    pub fn extract(&mut self, n: usize) -> Result<ArchiveEntry, Error> {
+        let data = self.extract_raw(n)?;
+        let file = DetachedTimestampFile::from_reader(&data[..])?;
+        let entry = &self.index[n];
+        if file.digest_type != entry.digest_type || file.timestamp.start_digest != entry.digest {
+            return Err(Error::IndexMismatch);
+        }
+        Ok(ArchiveEntry { name: entry.name.clone(), file })
+    }
+
+    /// Extracts the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use attestation::Attestation;
+    use timestamp::{Step, StepData, Timestamp};
+    use super::*;
+
+    fn pending_file(digest: u8, uri: &str) -> DetachedTimestampFile {
+        DetachedTimestampFile {
+            digest_type: DigestType::Sha256,
+            timestamp: Timestamp {
+                start_digest: vec![digest; 32],
+                first_step: Step {
+                    data: StepData::Attestation(Attestation::Pending { uri: uri.to_owned() }),
+                    output: vec![digest; 32],
+                    next: vec![]
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut archive = Archive::new();
+        archive.push(Some("a.txt".to_owned()), pending_file(1, "https://a.example.com"));
+        archive.push(None, pending_file(2, "https://b.example.com"));
+        archive.push(Some("c.txt".to_owned()), pending_file(3, "https://c.example.com"));
+
+        let mut data = vec![];
+        archive.to_writer(&mut data).unwrap();
+        assert_eq!(Archive::from_reader(&data[..]).unwrap(), archive);
+
+        let mut reader = ArchiveReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.index().len(), 3);
+        assert_eq!(reader.find(&[2; 32]), Some(1));
+        assert_eq!(reader.extract(2).unwrap(), archive.entries[2]);
+        assert_eq!(reader.extract(1).unwrap(), archive.entries[1]);
+        assert!(reader.extract(3).is_err());
+
+        let mut ots = vec![];
+        archive.entries[0].file.to_writer(&mut ots).unwrap();
+        assert_eq!(reader.extract_raw(0).unwrap(), ots);
+    }
+
+    #[test]
+    fn bad_index() {
+        // A single entry whose location comes from the index alone
+        let archive_with = |offset: usize, len: usize| {
+            let mut ser = ser::Serializer::new(vec![]);
+            ser.write_fixed_bytes(MAGIC).unwrap();
+            ser.write_uint(VERSION).unwrap();
+            ser.write_uint(1).unwrap();
+            ser.write_byte(DigestType::Sha256.to_tag()).unwrap();
+            ser.write_fixed_bytes(&[1; 32]).unwrap();
+            ser.write_bytes(&[]).unwrap();
+            ser.write_uint(offset).unwrap();
+            ser.write_uint(len).unwrap();
+            ser.write_fixed_bytes(b"short").unwrap();
+            ser.into_inner()
+        };
+
+        for &(offset, len) in &[(0, usize::MAX), (usize::MAX, 1), (3, 3), (6, 0)] {
+            let data = archive_with(offset, len);
+            let mut reader = ArchiveReader::new(Cursor::new(&data)).unwrap();
+            assert!(matches!(reader.extract_raw(0), Err(Error::Truncated)));
+        }
+        let data = archive_with(1, 3);
+        let mut reader = ArchiveReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.extract_raw(0).unwrap(), b"hor");
+    }
+}
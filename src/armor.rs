@@ -0,0 +1,180 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Armor
+//!
+//! A text encoding of timestamp files, for pasting proofs into emails,
+//! tickets and the like. It follows OpenPGP's ASCII armor: the binary
+//! file is base64-encoded in lines of 64 characters between header and
+//! footer lines, followed by a CRC-24 checksum.
+//!
+//! ```text
+//! -----BEGIN OPENTIMESTAMPS PROOF-----
+//! AE9wZW5UaW1lc3RhbXBzAABQcm9vZgC/ieLohOiSlAEIpw3+acWg1igWeBq7bhd3
+//! ...
+//! bGVuZGFyLm9wZW50aW1lc3RhbXBzLm9yZw==
+//! =HCYR
+//! -----END OPENTIMESTAMPS PROOF-----
+//! ```
+//!
+//! When parsing, any text before the header or after the footer is
+//! ignored, as is whitespace around each line.
+//!
+
+use base64;
+use error::Error;
+use ser::DetachedTimestampFile;
+
+/// First line of an armored proof
+pub const HEADER: &str = "-----BEGIN OPENTIMESTAMPS PROOF-----";
+/// Last line of an armored proof
+pub const FOOTER: &str = "-----END OPENTIMESTAMPS PROOF-----";
+
+/// Number of base64 characters per line
+const LINE_LENGTH: usize = 64;
+
+/// The CRC-24 checksum from RFC 4880
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = 0xb704ce;
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864cfb;
+            }
+        }
+    }
+    crc & 0xffffff
+}
+
+impl DetachedTimestampFile {
+    /// Encodes the timestamp file as armored text, ending in a newline
+    pub fn to_armored(&self) -> String {
+        let mut data = vec![];
+        self.to_writer(&mut data).expect("writing to vec");
+        let crc = crc24(&data);
+
+        let encoded = base64::encode(&data);
+        let mut ret = String::with_capacity(encoded.len() + 2 * LINE_LENGTH);
+        ret.push_str(HEADER);
+        ret.push('\n');
+        for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+            // base64 output is ASCII, so every chunk is valid UTF-8
+            ret.push_str(::std::str::from_utf8(line).unwrap());
+            ret.push('\n');
+        }
+        ret.push('=');
+        ret.push_str(&base64::encode(&[(crc >> 16) as u8, (crc >> 8) as u8, crc as u8]));
+        ret.push('\n');
+        ret.push_str(FOOTER);
+        ret.push('\n');
+        ret
+    }
+
+    /// Decodes an armored timestamp file, ignoring any surrounding text
+    pub fn from_armored(s: &str) -> Result<DetachedTimestampFile, Error> {
+        let mut lines = s.lines().map(str::trim).skip_while(|&line| line != HEADER);
+        if lines.next().is_none() {
+            return Err(Error::MalformedArmor);
+        }
+
+        let mut encoded = String::new();
+        let mut checksum = None;
+        let mut saw_footer = false;
+        for line in lines.by_ref() {
+            if line == FOOTER {
+                saw_footer = true;
+                break;
+            }
+            if checksum.is_some() {
+                return Err(Error::MalformedArmor);
+            }
+            if let Some(crc) = line.strip_prefix('=') {
+                // Distinguish the checksum from a line of padding
+                if !crc.is_empty() && !crc.starts_with('=') {
+                    checksum = Some(base64::decode(crc).ok_or(Error::MalformedArmor)?);
+                    continue;
+                }
+            }
+            encoded.push_str(line);
+        }
+
+        if !saw_footer {
+            return Err(Error::MalformedArmor);
+        }
+        let checksum = match checksum {
+            Some(ref crc) if crc.len() == 3 => (crc[0] as u32) << 16 | (crc[1] as u32) << 8 | crc[2] as u32,
+            _ => return Err(Error::MalformedArmor)
+        };
+        let data = base64::decode(&encoded).ok_or(Error::MalformedArmor)?;
+        if crc24(&data) != checksum {
+            return Err(Error::ArmorChecksum);
+        }
+        DetachedTimestampFile::from_reader(&data[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tests::{LARGE_TEST, SMALL_TEST};
+    use super::*;
+
+    #[test]
+    fn crc() {
+        // Check value for the OpenPGP CRC-24
+        assert_eq!(crc24(b"123456789"), 0x21cf02);
+    }
+
+    #[test]
+    fn round_trip() {
+        for data in &[SMALL_TEST, LARGE_TEST] {
+            let ots = DetachedTimestampFile::from_reader(*data).unwrap();
+            let armored = ots.to_armored();
+            assert!(armored.starts_with(HEADER));
+            assert!(armored.lines().all(|line| line.len() <= LINE_LENGTH));
+            assert_eq!(DetachedTimestampFile::from_armored(&armored).unwrap(), ots);
+
+            // Quoting and indentation are tolerated
+            let pasted = format!("Here is the proof:\n\n{}\nThanks\n", armored.replace('\n', "\n    "));
+            assert_eq!(DetachedTimestampFile::from_armored(&pasted).unwrap(), ots);
+        }
+    }
+
+    #[test]
+    fn bad_armor() {
+        let ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let armored = ots.to_armored();
+
+        let missing_footer = armored.replace(&format!("{}\n", FOOTER), "");
+        assert!(matches!(DetachedTimestampFile::from_armored(&missing_footer), Err(Error::MalformedArmor)));
+        // Cut off directly after the checksum line
+        let end = armored.find(FOOTER).unwrap();
+        assert!(matches!(DetachedTimestampFile::from_armored(&armored[..end]), Err(Error::MalformedArmor)));
+        assert!(matches!(DetachedTimestampFile::from_armored(armored[..end].trim_end()), Err(Error::MalformedArmor)));
+        assert!(matches!(DetachedTimestampFile::from_armored(&armored[HEADER.len()..]), Err(Error::MalformedArmor)));
+
+        let checksum_line = armored.lines().find(|line| line.starts_with('=')).unwrap();
+        let no_checksum = armored.replace(&format!("{}\n", checksum_line), "");
+        assert!(matches!(DetachedTimestampFile::from_armored(&no_checksum), Err(Error::MalformedArmor)));
+
+        // Flip a bit in the body
+        let mut lines: Vec<String> = armored.lines().map(String::from).collect();
+        let first = lines[1].clone();
+        let flipped = if first.starts_with('A') { 'B' } else { 'A' };
+        lines[1] = format!("{}{}", flipped, &first[1..]);
+        let corrupted = lines.join("\n");
+        assert!(matches!(DetachedTimestampFile::from_armored(&corrupted), Err(Error::ArmorChecksum)));
+    }
+}
@@ -20,20 +20,67 @@
 
 use std::fmt;
 use std::io::{Read, Write};
+use std::sync::RwLock;
 
 use error::Error;
 use hex::Hexed;
 use ser;
 
 /// Size in bytes of the tag identifying the attestation type
-const TAG_SIZE: usize = 8;
+pub const TAG_SIZE: usize = 8;
 /// Maximum length of a URI in a "pending" attestation
 const MAX_URI_LEN: usize = 1000;
 
 /// Tag indicating a Bitcoin attestation
-const BITCOIN_TAG: &'static [u8] = b"\x05\x88\x96\x0d\x73\xd7\x19\x01";
+const BITCOIN_TAG: &[u8] = b"\x05\x88\x96\x0d\x73\xd7\x19\x01";
 /// Tag indicating a pending attestation
-const PENDING_TAG: &'static [u8] = b"\x83\xdf\xe3\x0d\x2e\xf9\x0c\x8e";
+const PENDING_TAG: &[u8] = b"\x83\xdf\xe3\x0d\x2e\xf9\x0c\x8e";
+/// Tag indicating an Ethereum attestation
+const ETHEREUM_TAG: &[u8] = b"\x30\xfe\x80\x87\xb5\xc7\xea\xd7";
+
+/// Attestation types which are known of but not supported, and so are
+/// parsed as `Attestation::Unknown`, with their names
+const KNOWN_TAGS: &[(&[u8], &str)] = &[
+    (b"\x06\x86\x9a\x0d\x73\xd7\x1b\x45", "Litecoin")
+];
+
+/// Names registered by applications, which take precedence over `KNOWN_TAGS`
+static REGISTERED_TAGS: RwLock<Vec<(Vec<u8>, String)>> = RwLock::new(Vec::new());
+
+/// Registers a name for an attestation type this library does not
+/// support, used whenever an `Attestation::Unknown` with that tag is
+/// displayed. Replaces any name previously registered for it.
+pub fn register_tag_name(tag: &[u8], name: &str) {
+    let mut registered = REGISTERED_TAGS.write().unwrap_or_else(|e| e.into_inner());
+    registered.retain(|(known, _)| known[..] != *tag);
+    registered.push((tag.to_vec(), name.to_owned()));
+}
+
+/// The name of an attestation type this library knows of but does not
+/// support, either registered with `register_tag_name` or built in
+pub fn tag_name(tag: &[u8]) -> Option<String> {
+    let registered = REGISTERED_TAGS.read().unwrap_or_else(|e| e.into_inner());
+    match registered.iter().find(|(known, _)| known[..] == *tag) {
+        Some((_, name)) => Some(name.clone()),
+        None => KNOWN_TAGS.iter().find(|&&(known, _)| known == tag).map(|&(_, name)| name.to_owned())
+    }
+}
+
+/// Checks that a pending attestation's URI uses only allowed characters
+pub fn validate_uri(uri: &str) -> Result<(), Error> {
+    // This validation logic copied from python-opentimestamps. Peter comments
+    // that he is deliberately avoiding ?, &, @, etc., to "keep us out of trouble"
+    for ch in uri.chars() {
+        match ch {
+            'a'..='z' => {}
+            'A'..='Z' => {}
+            '0'..='9' => {}
+            '.' | '-' | '_' | '/' | ':' => {},
+            x => return Err(Error::InvalidUriChar(x))
+        }
+    }
+    Ok(())
+}
 
 /// An attestation that some data existed at some time
 #[allow(missing_docs)]
@@ -54,6 +101,12 @@ pub enum Attestation {
     Pending {
         uri: String
     },
+    /// An attestation from an Ethereum block header, analogous to a Bitcoin
+    /// one. These are deprecated and no longer produced, but appear in old
+    /// proofs. This library does not verify them.
+    Ethereum {
+        height: usize
+    },
     /// An unknown attestation that we just store straight
     Unknown {
         tag: Vec<u8>,
@@ -66,35 +119,44 @@ impl Attestation {
     pub fn deserialize<R: Read>(deser: &mut ser::Deserializer<R>) -> Result<Attestation, Error> {
         let tag = deser.read_fixed_bytes(TAG_SIZE)?;
         let len = deser.read_uint()?;
+        let max = deser.limits().max_attestation_payload;
+        if len > max {
+            return Err(Error::BadLength { min: 0, max, val: len });
+        }
+
+        if tag != BITCOIN_TAG && tag != PENDING_TAG && tag != ETHEREUM_TAG {
+            return Ok(Attestation::Unknown {
+                tag,
+                data: deser.read_fixed_bytes(len)?
+            });
+        }
 
-        if tag == BITCOIN_TAG {
-            let height = deser.read_uint()?;
-            Ok(Attestation::Bitcoin {
-                height: height
-            })
+        // Parse known payloads separately, so that a payload length which
+        // disagrees with its contents cannot desynchronize the proof
+        let payload = deser.read_fixed_bytes(len)?;
+        let mut payload_deser = ser::Deserializer::with_limits(&payload[..], *deser.limits());
+        let ret = if tag == BITCOIN_TAG {
+            let height = payload_deser.read_uint()?;
+            Attestation::Bitcoin {
+                height
+            }
         } else if tag == PENDING_TAG {
-            // This validation logic copied from python-opentimestamps. Peter comments
-            // that he is deliberately avoiding ?, &, @, etc., to "keep us out of trouble"
-            let uri_bytes = deser.read_bytes(0, MAX_URI_LEN)?;
+            let uri_bytes = payload_deser.read_bytes(0, MAX_URI_LEN)?;
             let uri_string = String::from_utf8(uri_bytes)?;
-            for ch in uri_string.chars() {
-                match ch {
-                    'a'...'z' => {}
-                    'A'...'Z' => {}
-                    '0'...'9' => {}
-                    '.' | '-' | '_' | '/' | ':' => {},
-                    x => return Err(Error::InvalidUriChar(x))
-                }
-            }
-            Ok(Attestation::Pending {
+            validate_uri(&uri_string)?;
+            Attestation::Pending {
                 uri: uri_string
-            })
+            }
         } else {
-            Ok(Attestation::Unknown {
-                tag: tag,
-                data: deser.read_fixed_bytes(len)?
-            })
+            let height = payload_deser.read_uint()?;
+            Attestation::Ethereum {
+                height
+            }
+        };
+        if deser.limits().strict {
+            payload_deser.check_eof()?;
         }
+        Ok(ret)
     }
 
     /// Serialize an attestation
@@ -111,6 +173,11 @@ impl Attestation {
                 byte_ser.write_bytes(uri.as_bytes())?;
                 ser.write_bytes(&byte_ser.into_inner())
             }
+            Attestation::Ethereum { height } => {
+                ser.write_fixed_bytes(ETHEREUM_TAG)?;
+                byte_ser.write_uint(height)?;
+                ser.write_bytes(&byte_ser.into_inner())
+            }
             Attestation::Unknown { ref tag, ref data } => {
                 ser.write_fixed_bytes(tag)?;
                 ser.write_bytes(data)
@@ -124,8 +191,74 @@ impl fmt::Display for Attestation {
         match *self {
             Attestation::Bitcoin { height } => write!(f, "Bitcoin block {}", height),
             Attestation::Pending { ref uri } => write!(f, "Pending: update URI {}", uri),
-            Attestation::Unknown { ref tag, ref data } => write!(f, "unknown attestation type {}: {}", Hexed(tag), Hexed(data)),
+            Attestation::Ethereum { height } => write!(f, "Ethereum block {}", height),
+            Attestation::Unknown { ref tag, ref data } => match tag_name(tag) {
+                Some(name) => write!(f, "unsupported {} attestation: {}", name, Hexed(data)),
+                None => write!(f, "unknown attestation type {}: {}", Hexed(tag), Hexed(data))
+            },
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ethereum() {
+        let data = b"\x30\xfe\x80\x87\xb5\xc7\xea\xd7\x03\xc0\x84\x3d";
+        let mut deser = ser::Deserializer::new(&data[..]);
+        let attest = Attestation::deserialize(&mut deser).unwrap();
+        assert_eq!(attest, Attestation::Ethereum { height: 1000000 });
+        assert_eq!(format!("{}", attest), "Ethereum block 1000000");
+
+        let mut ser = ser::Serializer::new(vec![]);
+        attest.serialize(&mut ser).unwrap();
+        assert_eq!(ser.into_inner(), &data[..]);
+    }
+
+    #[test]
+    fn payload_length() {
+        // Bitcoin attestation at height 1 with a trailing byte in the payload
+        let data = b"\x05\x88\x96\x0d\x73\xd7\x19\x01\x02\x01\x00";
+        let mut deser = ser::Deserializer::new(&data[..]);
+        match Attestation::deserialize(&mut deser) {
+            Err(Error::TrailingBytes) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        let limits = ser::Limits { strict: false, ..Default::default() };
+        let mut deser = ser::Deserializer::with_limits(&data[..], limits);
+        assert_eq!(Attestation::deserialize(&mut deser).unwrap(), Attestation::Bitcoin { height: 1 });
+        deser.check_eof().unwrap();
+
+        // A payload too short for its contents is an error either way
+        let data = b"\x05\x88\x96\x0d\x73\xd7\x19\x01\x00\x01";
+        let mut deser = ser::Deserializer::with_limits(&data[..], limits);
+        match Attestation::deserialize(&mut deser) {
+            Err(Error::Truncated) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn known_tags() {
+        let litecoin = Attestation::Unknown { tag: b"\x06\x86\x9a\x0d\x73\xd7\x1b\x45".to_vec(), data: vec![0x01] };
+        assert_eq!(format!("{}", litecoin), "unsupported Litecoin attestation: 01");
+        let unknown = Attestation::Unknown { tag: vec![0; 8], data: vec![0x01] };
+        assert_eq!(format!("{}", unknown), "unknown attestation type 0000000000000000: 01");
+    }
+
+    #[test]
+    fn registered_tags() {
+        // A tag no other test uses, since the registry is global
+        let tag = b"\xa0\xa1\xa2\xa3\xa4\xa5\xa6\xa7";
+        let attest = Attestation::Unknown { tag: tag.to_vec(), data: vec![0x01] };
+        assert_eq!(tag_name(tag), None);
+        register_tag_name(tag, "Example");
+        assert_eq!(format!("{}", attest), "unsupported Example attestation: 01");
+        register_tag_name(tag, "Renamed");
+        assert_eq!(tag_name(tag), Some("Renamed".to_owned()));
+        assert_eq!(tag_name(b"\x06\x86\x9a\x0d\x73\xd7\x1b\x45"), Some("Litecoin".to_owned()));
+    }
+}
@@ -0,0 +1,97 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Base64
+//!
+//! Standard base64 with padding, as used by HTTP basic authentication
+//! and armored proofs
+//!
+
+const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Base64-encodes some data
+pub fn encode(data: &[u8]) -> String {
+    let mut ret = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as usize) << 16
+              | (*chunk.get(1).unwrap_or(&0) as usize) << 8
+              | *chunk.get(2).unwrap_or(&0) as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(CHARS[(n >> (18 - 6 * i)) & 0x3f] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+/// Decodes padded base64. Whitespace is not allowed, and neither are
+/// nonzero bits after the data, so each input has one encoding.
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 4 != 0 {
+        return None;
+    }
+    // Padding may only end the string
+    let data = s.trim_end_matches('=');
+    if s.len() - data.len() > 2 {
+        return None;
+    }
+
+    let mut ret = Vec::with_capacity(s.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in data.chars() {
+        let val = CHARS.iter().position(|&x| x as char == c)? as u32;
+        acc = acc << 6 | val;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            ret.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    if acc != 0 {
+        return None;
+    }
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for &(data, enc) in &[(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"),
+                              (b"user:pass", "dXNlcjpwYXNz")] {
+            assert_eq!(encode(data), enc);
+            assert_eq!(decode(enc).unwrap(), data);
+        }
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode("Zg="), None);
+        assert_eq!(decode("Zg==Zg=="), None);
+        assert_eq!(decode("Z==="), None);
+        assert_eq!(decode("Zm9*"), None);
+        // Leftover bits must be zero
+        assert_eq!(decode("Zh=="), None);
+        assert_eq!(decode("Zm9="), None);
+    }
+}
@@ -0,0 +1,257 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # OpenTimestamps Client
+//!
+//! Command-line client modelled on the Python `ots` tool. Only the `info`
+//! and `verify` subcommands are implemented; verification uses a local
+//! Bitcoin Core node. `stamp` and `upgrade` are not, since both talk to
+//! calendars over HTTPS and this library has no HTTPS client.
+//! Applications that do have one can upgrade proofs by implementing
+//! `calendar::CalendarSource` and calling `calendar::upgrade`.
+//!
+
+// Coding conventions
+#![deny(non_upper_case_globals)]
+#![deny(non_camel_case_types)]
+#![deny(non_snake_case)]
+#![deny(unused_mut)]
+#![deny(missing_docs)]
+
+extern crate env_logger;
+extern crate opentimestamps as ots;
+
+use std::{env, io, process};
+use std::io::Write;
+use std::path::PathBuf;
+
+use ots::bitcoind::BitcoindSource;
+use ots::error::Error;
+use ots::DetachedTimestampFile;
+
+const USAGE: &str = "\
+Usage:
+    ots info <file.ots>
+    ots verify [options] <file.ots>
+
+Verify options:
+    --bitcoind <host:port>      Bitcoin Core RPC address [default: 127.0.0.1:8332]
+    --cookie <path>             RPC cookie file [default: ~/.bitcoin/.cookie]
+    --rpcuser <user>            RPC username, instead of a cookie file
+    --rpcpassword <pass>        RPC password, instead of a cookie file";
+
+/// A reason to exit unsuccessfully, reported on stderr
+#[derive(Debug)]
+struct Failure(String);
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Failure {
+        Failure(e.to_string())
+    }
+}
+
+fn usage() -> Failure {
+    Failure(USAGE.to_owned())
+}
+
+fn load(path: &str) -> Result<DetachedTimestampFile, Failure> {
+    DetachedTimestampFile::read_from_path(path).map_err(|e| Failure(format!("Failed to parse {}: {}", path, e)))
+}
+
+fn info<W: Write>(args: &[String], out: &mut W) -> Result<(), Failure> {
+    if args.len() != 1 {
+        return Err(usage());
+    }
+    writeln!(out, "{}", load(&args[0])?)?;
+    Ok(())
+}
+
+fn verify<W: Write, E: Write>(args: &[String], out: &mut W, err: &mut E) -> Result<(), Failure> {
+    let mut addr = "127.0.0.1:8332".to_owned();
+    let mut cookie = None;
+    let mut user = None;
+    let mut pass = None;
+    let mut path = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let slot = match &arg[..] {
+            "--bitcoind" => &mut addr,
+            "--cookie" => cookie.get_or_insert_with(String::new),
+            "--rpcuser" => user.get_or_insert_with(String::new),
+            "--rpcpassword" => pass.get_or_insert_with(String::new),
+            _ if path.is_none() && !arg.starts_with("--") => {
+                path = Some(arg.clone());
+                continue;
+            }
+            _ => return Err(usage())
+        };
+        *slot = match iter.next() {
+            Some(val) => val.clone(),
+            None => return Err(usage())
+        };
+    }
+    let path = match path {
+        Some(path) => path,
+        None => return Err(usage())
+    };
+    // A cookie file is an alternative to a username and password
+    if cookie.is_some() && (user.is_some() || pass.is_some()) {
+        return Err(usage());
+    }
+    let ots = load(&path)?;
+
+    // Check the timestamped file itself, if it is alongside the proof
+    if let Some(target) = DetachedTimestampFile::target_path(&path) {
+        match ots.check_file(&target) {
+            Ok(()) => writeln!(err, "Assuming target filename is {}", target.display())?,
+            Err(ref e) if matches!(e.root(), Error::CommitmentMismatch) => return Err(Failure(format!("File {} does not match its timestamp!", target.display()))),
+            Err(e) => writeln!(err, "Not checking {}: {}", target.display(), e)?
+        }
+    }
+
+    let source = match (user, pass) {
+        (Some(user), Some(pass)) => BitcoindSource::new(&addr, &user, &pass),
+        (None, None) => {
+            let cookie = match cookie {
+                Some(cookie) => PathBuf::from(cookie),
+                None => match env::var_os("HOME") {
+                    Some(home) => PathBuf::from(home).join(".bitcoin").join(".cookie"),
+                    None => return Err(Failure("No cookie file given and $HOME is not set".to_owned()))
+                }
+            };
+            BitcoindSource::from_cookie_file(&addr, &cookie)
+                .map_err(|e| Failure(format!("Failed to read cookie file {}: {}", cookie.display(), e)))?
+        }
+        _ => return Err(usage())
+    };
+
+    match ots::verify::verify(&ots.timestamp, &source) {
+        Ok(report) => {
+            let earliest = report.earliest();
+            writeln!(out, "Success! Bitcoin block {} attests existence as of Unix time {}",
+                     earliest.height, earliest.time)?;
+            Ok(())
+        }
+        Err(e) => Err(Failure(format!("Verification failed: {}", e)))
+    }
+}
+
+/// Runs the client on its arguments, not including the program name.
+/// Results go to `out` and diagnostics to `err`.
+fn run<W: Write, E: Write>(args: &[String], out: &mut W, err: &mut E) -> Result<(), Failure> {
+    if args.is_empty() {
+        return Err(usage());
+    }
+    match &args[0][..] {
+        "info" => info(&args[1..], out),
+        "verify" => verify(&args[1..], out, err),
+        "stamp" | "upgrade" => Err(Failure(format!("ots {} is not supported: this client has no HTTPS client to contact calendars", args[0]))),
+        _ => Err(usage())
+    }
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(Failure(msg)) = run(&args, &mut io::stdout(), &mut io::stderr()) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ots::attestation::Attestation;
+    use ots::op::Op;
+    use ots::ser::DigestType;
+    use ots::timestamp::{Step, StepData, Timestamp};
+    use super::*;
+
+    /// Runs the client, returning its success, stdout and stderr
+    fn run_args(args: &[&str]) -> (bool, String, String) {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let (mut out, mut err) = (vec![], vec![]);
+        let ok = match run(&args, &mut out, &mut err) {
+            Ok(()) => true,
+            Err(Failure(msg)) => {
+                err.extend_from_slice(msg.as_bytes());
+                false
+            }
+        };
+        (ok, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    #[test]
+    fn info_and_verify() {
+        let dir = env::temp_dir().join(format!("ots-cli-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("hello.txt");
+        fs::write(&target, b"Hello World!\n").unwrap();
+
+        let digest = Op::Sha256.execute(b"Hello World!\n");
+        let ots = DetachedTimestampFile {
+            digest_type: DigestType::Sha256,
+            timestamp: Timestamp {
+                start_digest: digest.clone(),
+                first_step: Step {
+                    data: StepData::Attestation(Attestation::Pending { uri: "https://example.com".to_owned() }),
+                    output: digest,
+                    next: vec![]
+                }
+            }
+        };
+        let proof = DetachedTimestampFile::proof_path(&target);
+        ots.write_to_path(&proof).unwrap();
+        let proof = proof.to_str().unwrap();
+
+        let (ok, out, err) = run_args(&["info", proof]);
+        assert!(ok);
+        assert_eq!(out, format!("{}\n", ots));
+        assert!(err.is_empty());
+
+        // The proof is only pending, so verification fails after the
+        // target file is checked, without contacting a node
+        let (ok, out, err) = run_args(&["verify", "--rpcuser", "u", "--rpcpassword", "p", proof]);
+        assert!(!ok);
+        assert!(out.is_empty());
+        assert!(err.starts_with("Assuming target filename is"));
+        assert!(err.contains("Verification failed"));
+
+        fs::write(&target, b"Goodbye World!\n").unwrap();
+        let (ok, out, err) = run_args(&["verify", "--rpcuser", "u", "--rpcpassword", "p", proof]);
+        assert!(!ok);
+        assert!(out.is_empty());
+        assert!(err.contains("does not match its timestamp"));
+
+        let (ok, _, err) = run_args(&["verify", "--cookie", "c", "--rpcuser", "u", "--rpcpassword", "p", proof]);
+        assert!(!ok);
+        assert!(err.starts_with("Usage:"));
+        let (ok, _, err) = run_args(&["upgrade", proof]);
+        assert!(!ok);
+        assert!(err.contains("not supported"));
+
+        let (ok, _, err) = run_args(&["info"]);
+        assert!(!ok);
+        assert!(err.starts_with("Usage:"));
+        let (ok, _, err) = run_args(&["info", "nonexistent.ots"]);
+        assert!(!ok);
+        assert!(err.starts_with("Failed to parse nonexistent.ots"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Bitcoin Core
+//!
+//! Block header source backed by a Bitcoin Core node's JSON-RPC interface.
+//! The node is trusted completely, so this is intended for use with a
+//! local node. Only plain HTTP is supported, which is all that Bitcoin
+//! Core speaks anyway.
+//!
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::{self, Value};
+
+use base64;
+use error::Error;
+use hex::{self, Hexed};
+use verify::{BlockHeader, BlockHeaderSource};
+
+/// Default time to wait on the node before giving up
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+/// A connection to a Bitcoin Core node
+pub struct BitcoindSource {
+    addr: String,
+    auth: String,
+    timeout: Duration
+}
+
+impl BitcoindSource {
+    /// Constructs a new source which connects to `addr` (a `host:port`
+    /// string) using the given RPC credentials
+    pub fn new(addr: &str, user: &str, pass: &str) -> BitcoindSource {
+        BitcoindSource {
+            addr: addr.to_owned(),
+            auth: base64::encode(format!("{}:{}", user, pass).as_bytes()),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS)
+        }
+    }
+
+    /// Constructs a new source using the credentials in a Bitcoin Core
+    /// `.cookie` file
+    pub fn from_cookie_file<P: AsRef<Path>>(addr: &str, path: P) -> Result<BitcoindSource, Error> {
+        let cookie = fs::read_to_string(path)?;
+        let cookie = cookie.trim();
+        match cookie.find(':') {
+            Some(idx) => Ok(BitcoindSource::new(addr, &cookie[..idx], &cookie[idx + 1..])),
+            None => Err(Error::Rpc("malformed cookie file".to_owned()))
+        }
+    }
+
+    /// Sets the read/write timeout for requests to the node
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Makes a single JSON-RPC call, returning its `result` field
+    fn call(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let body = json!({ "jsonrpc": "1.0", "id": "ots", "method": method, "params": params }).to_string();
+        trace!("Calling bitcoind: {}", body);
+
+        let mut stream = TcpStream::connect(&self.addr[..])?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(stream, "POST / HTTP/1.0\r\n\
+                        Host: {}\r\n\
+                        Authorization: Basic {}\r\n\
+                        Content-Type: application/json\r\n\
+                        Content-Length: {}\r\n\
+                        Connection: close\r\n\r\n{}",
+               self.addr, self.auth, body.len(), body)?;
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+        let split = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(idx) => idx,
+            None => return Err(Error::Rpc("malformed HTTP response".to_owned()))
+        };
+        let status = String::from_utf8_lossy(&response[..split]).lines().next().unwrap_or("").to_owned();
+
+        // Bitcoin Core reports RPC errors with a non-200 status and a JSON
+        // body, but authentication failures have no body at all
+        let mut reply: Value = match serde_json::from_slice(&response[split + 4..]) {
+            Ok(reply) => reply,
+            Err(_) => return Err(Error::Rpc(status))
+        };
+        if !reply["error"].is_null() {
+            return Err(Error::Rpc(match reply["error"]["message"].as_str() {
+                Some(msg) => msg.to_owned(),
+                None => reply["error"].to_string()
+            }));
+        }
+        Ok(reply["result"].take())
+    }
+}
+
+impl BlockHeaderSource for BitcoindSource {
+    fn block_header(&self, height: usize) -> Result<BlockHeader, Error> {
+        let hash = match self.call("getblockhash", json!([height])) {
+            Ok(Value::String(hash)) => hash,
+            Ok(_) => return Err(Error::Rpc("getblockhash did not return a string".to_owned())),
+            // Bitcoin Core's RPC_INVALID_PARAMETER, "Block height out of range"
+            Err(Error::Rpc(ref msg)) if msg.contains("out of range") => return Err(Error::NoHeader(height)),
+            Err(e) => return Err(e)
+        };
+        let header = match self.call("getblockheader", json!([hash, false]))? {
+            Value::String(hex) => {
+                let data = hex::decode(&hex).map_err(|e| Error::Rpc(format!("bad hex from node: {}", e)))?;
+                BlockHeader::from_bytes(&data)?
+            }
+            _ => return Err(Error::Rpc("getblockheader did not return a string".to_owned()))
+        };
+
+        let mut block_hash = header.block_hash();
+        block_hash.reverse();
+        if format!("{}", Hexed(&block_hash)) != hash {
+            return Err(Error::Rpc(format!("node returned wrong header for block {}", hash)));
+        }
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+
+    /// Serves one canned HTTP response per connection, returning the requests received
+    fn serve(responses: Vec<(&'static str, String)>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut content_len = 0;
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.to_lowercase().starts_with("content-length:") {
+                        content_len = line[15..].trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body_in = vec![0; content_len];
+                reader.read_exact(&mut body_in).unwrap();
+                request.push_str(&String::from_utf8(body_in).unwrap());
+                requests.push(request);
+
+                let mut stream = reader.into_inner();
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body).unwrap();
+            }
+            requests
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn header_lookup() {
+        let (addr, handle) = serve(vec![
+            ("200 OK", format!("{{\"result\":\"{}\",\"error\":null,\"id\":\"ots\"}}", GENESIS_HASH)),
+            ("200 OK", format!("{{\"result\":\"{}\",\"error\":null,\"id\":\"ots\"}}", GENESIS_HEADER)),
+            ("500 Internal Server Error", "{\"result\":null,\"error\":{\"code\":-8,\"message\":\"Block height out of range\"},\"id\":\"ots\"}".to_owned()),
+            ("401 Unauthorized", String::new())
+        ]);
+
+        let source = BitcoindSource::new(&addr, "user", "pass");
+        let header = source.block_header(0).unwrap();
+        assert_eq!(header.time, 1231006505);
+        match source.block_header(1000000) {
+            Err(Error::NoHeader(1000000)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        match source.block_header(0) {
+            Err(Error::Rpc(ref msg)) if msg.contains("401") => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        let requests = handle.join().unwrap();
+        assert!(requests[0].contains("Authorization: Basic dXNlcjpwYXNz\r\n"));
+        assert!(requests[0].contains("\"method\":\"getblockhash\""));
+        assert!(requests[0].contains("\"params\":[0]"));
+        assert!(requests[1].contains("\"method\":\"getblockheader\""));
+        assert!(requests[1].contains(GENESIS_HASH));
+    }
+}
@@ -0,0 +1,306 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Calendars
+//!
+//! A pending attestation names a calendar server which has promised to
+//! eventually commit to the attested data in the Bitcoin blockchain. Once
+//! it has, the calendar serves the rest of the proof at
+//! `<uri>/timestamp/<hex commitment>`, which can be spliced into the
+//! original timestamp to "upgrade" it.
+//!
+//! This library does not include an HTTP client; applications supply
+//! one by implementing `CalendarSource`. The response body is parsed
+//! with `Timestamp::deserialize`, starting from the commitment.
+//!
+//! Since the URIs come from the proof, which may be malicious, only
+//! calendars allowed by a `CalendarPolicy` are ever contacted. Their
+//! responses are not trusted either: they must start from the requested
+//! commitment and pass `Timestamp::validate` before being spliced in.
+//!
+
+use attestation::Attestation;
+use error::Error;
+use timestamp::{Step, StepData, Timestamp};
+
+/// Something which can fetch completed timestamps from calendar servers
+pub trait CalendarSource {
+    /// Fetches the timestamp for `commitment` from the calendar at `uri`.
+    /// Returns `None` if the calendar does not (yet) have one.
+    fn get_timestamp(&self, uri: &str, commitment: &[u8]) -> Result<Option<Timestamp>, Error>;
+}
+
+/// Calendars which published OpenTimestamps clients trust by default
+const DEFAULT_CALENDARS: &[&str] = &[
+    "https://*.calendar.opentimestamps.org",
+    "https://*.calendar.eternitywall.com",
+    "https://*.calendar.catallaxy.com"
+];
+
+/// Rules deciding which calendar URIs may be contacted. A URI must match
+/// the allowlist (unless it is unrestricted) and must not match the
+/// denylist. Patterns are URIs whose host may begin with `*.`, matching
+/// any subdomain; the scheme and path must match exactly. As in
+/// python-opentimestamps, URIs with query strings or fragments never match.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CalendarPolicy {
+    allow: Option<Vec<String>>,
+    deny: Vec<String>
+}
+
+impl Default for CalendarPolicy {
+    /// The public calendars trusted by python-opentimestamps
+    fn default() -> CalendarPolicy {
+        CalendarPolicy::allow_only(DEFAULT_CALENDARS.iter().cloned())
+    }
+}
+
+impl CalendarPolicy {
+    /// A policy which allows any calendar not explicitly denied
+    pub fn allow_all() -> CalendarPolicy {
+        CalendarPolicy { allow: None, deny: vec![] }
+    }
+
+    /// A policy which allows only calendars matching the given patterns
+    pub fn allow_only<I, S>(patterns: I) -> CalendarPolicy
+        where I: IntoIterator<Item = S>,
+              S: Into<String>
+    {
+        CalendarPolicy {
+            allow: Some(patterns.into_iter().map(Into::into).collect()),
+            deny: vec![]
+        }
+    }
+
+    /// Adds a pattern to the allowlist. Has no effect on an
+    /// unrestricted policy.
+    pub fn allow<S: Into<String>>(&mut self, pattern: S) {
+        if let Some(ref mut allow) = self.allow {
+            allow.push(pattern.into());
+        }
+    }
+
+    /// Adds a pattern to the denylist
+    pub fn deny<S: Into<String>>(&mut self, pattern: S) {
+        self.deny.push(pattern.into());
+    }
+
+    /// Whether the calendar at `uri` may be contacted
+    pub fn is_allowed(&self, uri: &str) -> bool {
+        if self.deny.iter().any(|pattern| uri_matches(pattern, uri)) {
+            return false;
+        }
+        match self.allow {
+            Some(ref allow) => allow.iter().any(|pattern| uri_matches(pattern, uri)),
+            None => true
+        }
+    }
+}
+
+/// Splits a URI into scheme, host and path
+fn split_uri(uri: &str) -> Option<(&str, &str, &str)> {
+    let idx = uri.find("://")?;
+    let (scheme, rest) = (&uri[..idx], &uri[idx + 3..]);
+    match rest.find('/') {
+        Some(idx) => Some((scheme, &rest[..idx], &rest[idx..])),
+        None => Some((scheme, rest, ""))
+    }
+}
+
+fn uri_matches(pattern: &str, uri: &str) -> bool {
+    if uri.contains('?') || uri.contains('#') {
+        return false;
+    }
+    let (p_scheme, p_host, p_path) = match split_uri(pattern) {
+        Some(parts) => parts,
+        None => return false
+    };
+    let (scheme, host, path) = match split_uri(uri) {
+        Some(parts) => parts,
+        None => return false
+    };
+
+    let host_matches = if let Some(suffix) = p_host.strip_prefix('*') {
+        host.len() > suffix.len() && host.ends_with(suffix)
+    } else {
+        host == p_host
+    };
+    scheme == p_scheme && host_matches && path == p_path
+}
+
+/// Upgrades every pending attestation in a timestamp for which its
+/// calendar has a completed proof. As in python-opentimestamps, the
+/// pending attestation is kept, and the new proof is added next to it
+/// as a fork. Calendars not allowed by `policy` are skipped. Returns
+/// whether any new attestations were added.
+pub fn upgrade<C: CalendarSource + ?Sized>(timestamp: &mut Timestamp, calendar: &C, policy: &CalendarPolicy) -> Result<bool, Error> {
+    let mut existing: Vec<Attestation> = timestamp.attestations().map(|(_, attest)| attest.clone()).collect();
+
+    let mut changed = false;
+    upgrade_recurse(&mut timestamp.first_step, calendar, policy, &mut existing, &mut changed)?;
+    Ok(changed)
+}
+
+fn upgrade_recurse<C: CalendarSource + ?Sized>(step: &mut Step, calendar: &C, policy: &CalendarPolicy, existing: &mut Vec<Attestation>, changed: &mut bool) -> Result<(), Error> {
+    let uri = match step.data {
+        StepData::Attestation(Attestation::Pending { ref uri }) => uri.clone(),
+        _ => {
+            for next in &mut step.next {
+                upgrade_recurse(next, calendar, policy, existing, changed)?;
+            }
+            return Ok(());
+        }
+    };
+
+    if !policy.is_allowed(&uri) {
+        warn!("Not contacting calendar {}, which is not allowed by policy", uri);
+        return Ok(());
+    }
+
+    let upgraded = match calendar.get_timestamp(&uri, &step.output)? {
+        Some(upgraded) => upgraded,
+        None => return Ok(())
+    };
+    if upgraded.start_digest != step.output {
+        return Err(Error::CommitmentMismatch);
+    }
+    upgraded.validate()?;
+
+    let new_attestations: Vec<Attestation> = upgraded.attestations()
+                                                     .map(|(_, attest)| attest.clone())
+                                                     .filter(|attest| !existing.contains(attest))
+                                                     .collect();
+    if new_attestations.is_empty() {
+        return Ok(());
+    }
+    debug!("Upgraded commitment from {} with {} new attestations", uri, new_attestations.len());
+    existing.extend(new_attestations);
+    *changed = true;
+
+    let pending = step.clone();
+    *step = Step {
+        data: StepData::Fork,
+        output: pending.output.clone(),
+        next: vec![pending, upgraded.first_step]
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use op::Op;
+    use ser::DetachedTimestampFile;
+    use tests::SMALL_TEST;
+    use super::*;
+
+    /// Calendar which has completed only the commitments of one server
+    struct MockCalendar(&'static str);
+
+    impl CalendarSource for MockCalendar {
+        fn get_timestamp(&self, uri: &str, commitment: &[u8]) -> Result<Option<Timestamp>, Error> {
+            if uri != self.0 {
+                return Ok(None);
+            }
+            Ok(Some(completed(commitment)))
+        }
+    }
+
+    /// Calendar which answers with a proof for some other commitment
+    struct LyingCalendar;
+
+    impl CalendarSource for LyingCalendar {
+        fn get_timestamp(&self, _: &str, _: &[u8]) -> Result<Option<Timestamp>, Error> {
+            Ok(Some(completed(&[0; 32])))
+        }
+    }
+
+    fn completed(commitment: &[u8]) -> Timestamp {
+        let output = Op::Sha256.execute(commitment);
+        Timestamp {
+            start_digest: commitment.to_vec(),
+            first_step: Step {
+                data: StepData::Op(Op::Sha256),
+                output: output.clone(),
+                next: vec![Step {
+                    data: StepData::Attestation(Attestation::Bitcoin { height: 449397 }),
+                    output,
+                    next: vec![]
+                }]
+            }
+        }
+    }
+
+    #[test]
+    fn upgrade_pending() {
+        let mut ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let original = ots.clone();
+
+        let policy = CalendarPolicy::default();
+        assert!(!upgrade(&mut ots.timestamp, &MockCalendar("https://nowhere.example.com"), &policy).unwrap());
+        assert_eq!(ots, original);
+
+        let calendar = MockCalendar("https://alice.btc.calendar.opentimestamps.org");
+        let mut deny_alice = CalendarPolicy::default();
+        deny_alice.deny("https://alice.btc.calendar.opentimestamps.org");
+        assert!(!upgrade(&mut ots.timestamp, &calendar, &deny_alice).unwrap());
+        assert_eq!(ots, original);
+
+        assert!(upgrade(&mut ots.timestamp, &calendar, &policy).unwrap());
+        let attestations: Vec<_> = ots.timestamp.attestations().map(|(_, attest)| attest).collect();
+        assert_eq!(attestations.len(), 3);
+        assert!(attestations.contains(&&Attestation::Bitcoin { height: 449397 }));
+
+        // Nothing new the second time around
+        let upgraded = ots.clone();
+        assert!(!upgrade(&mut ots.timestamp, &calendar, &policy).unwrap());
+        assert_eq!(ots, upgraded);
+
+        match upgrade(&mut ots.timestamp, &LyingCalendar, &policy) {
+            Err(Error::CommitmentMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        assert_eq!(ots, upgraded);
+
+        // Upgraded proof survives serialization
+        let mut data = vec![];
+        ots.to_writer(&mut data).unwrap();
+        assert_eq!(DetachedTimestampFile::from_reader(&data[..]).unwrap(), ots);
+    }
+
+    #[test]
+    fn policy() {
+        let policy = CalendarPolicy::default();
+        assert!(policy.is_allowed("https://alice.btc.calendar.opentimestamps.org"));
+        assert!(policy.is_allowed("https://a.pool.calendar.opentimestamps.org"));
+        assert!(!policy.is_allowed("https://calendar.opentimestamps.org"));
+        assert!(!policy.is_allowed("http://alice.btc.calendar.opentimestamps.org"));
+        assert!(!policy.is_allowed("https://alice.btc.calendar.opentimestamps.org/evil"));
+        assert!(!policy.is_allowed("https://alice.btc.calendar.opentimestamps.org?x=y"));
+        assert!(!policy.is_allowed("https://evil.com/.calendar.opentimestamps.org"));
+        assert!(!policy.is_allowed("https://evilcalendar.opentimestamps.org"));
+        assert!(!policy.is_allowed("not a uri"));
+
+        let mut policy = CalendarPolicy::allow_all();
+        assert!(policy.is_allowed("https://anything.example.com"));
+        policy.deny("https://*.example.com");
+        assert!(!policy.is_allowed("https://anything.example.com"));
+        assert!(policy.is_allowed("https://example.org"));
+
+        let mut policy = CalendarPolicy::allow_only(vec!["https://ots.example.com"]);
+        assert!(policy.is_allowed("https://ots.example.com"));
+        assert!(!policy.is_allowed("https://ots2.example.com"));
+        policy.allow("https://ots2.example.com");
+        assert!(policy.is_allowed("https://ots2.example.com"));
+    }
+}
@@ -21,7 +21,9 @@ use std::error;
 use std::{fmt, io};
 use std::string::FromUtf8Error;
 
-/// Library-wide error structure
+/// Library-wide error structure. Parsing errors may be wrapped in
+/// `AtOffset`, `InStep` or `InHeader` to say where they occurred; match
+/// on `Error::root()` to find the underlying failure.
 #[allow(missing_docs)]
 #[derive(Debug)]
 pub enum Error {
@@ -37,16 +39,89 @@ pub enum Error {
     BadMagic(Vec<u8>),
     /// OTS file has version we don't understand
     BadVersion(usize),
+    /// A hex string contained a non-hex character
+    InvalidHexChar(char),
+    /// A hex string had an odd number of digits
+    OddHexLength(usize),
+    /// An armored proof was missing its header, footer or checksum line,
+    /// or had invalid base64
+    MalformedArmor,
+    /// An armored proof's checksum did not match its contents
+    ArmorChecksum,
     /// A byte vector had an invalid length
     BadLength { min: usize, max: usize, val: usize },
     /// Expected EOF but didn't get it
     TrailingBytes,
+    /// Data ended in the middle of an item
+    Truncated,
+    /// A variable-length integer was too large to represent
+    VarintOverflow,
+    /// A variable-length integer was encoded with more bytes than needed
+    NonMinimalVarint,
+    /// Parsing failed at the given byte offset
+    AtOffset { offset: usize, error: Box<Error> },
+    /// Parsing failed in the given step of a timestamp, counting from 1
+    InStep { index: usize, kind: &'static str, error: Box<Error> },
+    /// Parsing failed in the header of a timestamp file
+    InHeader(Box<Error>),
+    /// A proof exceeded one of the deserializer's limits
+    LimitExceeded { limit: &'static str, max: usize },
+    /// A timestamp step has the wrong number of successors
+    MalformedStep,
+    /// A timestamp step's output is not the result of executing it
+    OutputMismatch,
+    /// A timestamp, e.g. from a calendar, is for a different commitment
+    CommitmentMismatch,
+    /// An edit would leave a timestamp with no attestations
+    NoAttestations,
+    /// An archive index entry disagrees with the proof it points to
+    IndexMismatch,
+    /// Requested an archive entry past the end of the index
+    NoSuchEntry(usize),
+    /// A header source has no block at the given height
+    NoHeader(usize),
+    /// A Bitcoin attestation's commitment is not the Merkle root of its block
+    MerkleRootMismatch(usize),
+    /// Verification found no attestations it could check
+    NoVerifiableAttestation,
+    /// A custom attestation failed its registered parser or verifier
+    BadAttestation(String),
+    /// A block height is too large to be valid
+    BadHeight(usize),
+    /// A header at the given height does not link to its predecessor
+    BrokenChain(usize),
+    /// A header at the given height has the wrong difficulty target
+    BadDifficulty(usize),
+    /// A header at the given height does not meet its difficulty target
+    BadProofOfWork(usize),
+    /// SPV consensus parameters are unusable, for the given reason
+    BadParams(&'static str),
+    /// An SPV checkpoint at the given height is not at a difficulty adjustment
+    BadCheckpoint(usize),
+    /// A block header backend failed
+    Rpc(String),
+    /// A JSON timestamp did not have the expected structure
+    Json(String),
     /// UTF8
     Utf8(FromUtf8Error),
     /// I/O error
     Io(io::Error)
 }
 
+impl Error {
+    /// The underlying error, without any context about where it occurred.
+    /// Errors without context are returned as they are, so this is always
+    /// safe to match on, e.g. `matches!(e.root(), Error::BadMagic(_))`.
+    pub fn root(&self) -> &Error {
+        match *self {
+            Error::AtOffset { ref error, .. } => error.root(),
+            Error::InStep { ref error, .. } => error.root(),
+            Error::InHeader(ref error) => error.root(),
+            _ => self
+        }
+    }
+}
+
 impl From<FromUtf8Error> for Error {
     fn from(e: FromUtf8Error) -> Error {
         Error::Utf8(e)
@@ -68,8 +143,37 @@ impl fmt::Display for Error {
             Error::BadOpTag(t) => write!(f, "invalid op tag 0x{:02x}", t),
             Error::BadMagic(ref x) => write!(f, "bad magic bytes `{:?}`, is this a timestamp file?", x),
             Error::BadVersion(v) => write!(f, "version {} timestamps not understood", v),
+            Error::InvalidHexChar(c) => write!(f, "invalid character `{}` in hex string", c),
+            Error::OddHexLength(n) => write!(f, "hex string has odd length {}", n),
+            Error::MalformedArmor => f.write_str("malformed armored proof"),
+            Error::ArmorChecksum => f.write_str("armored proof checksum mismatch"),
             Error::BadLength { min, max, val } => write!(f, "length {} should be between {} and {} inclusive", val, min, max),
             Error::TrailingBytes => f.write_str("expected eof not"), // lol
+            Error::Truncated => f.write_str("unexpected end of data"),
+            Error::VarintOverflow => f.write_str("integer too large"),
+            Error::NonMinimalVarint => f.write_str("integer not minimally encoded"),
+            Error::AtOffset { offset, ref error } => write!(f, "at byte {}: {}", offset, error),
+            Error::InStep { index, kind, ref error } => write!(f, "in {} at step {}: {}", kind, index, error),
+            Error::InHeader(ref error) => write!(f, "in file header: {}", error),
+            Error::LimitExceeded { limit, max } => write!(f, "proof exceeds limit of {} {}", max, limit),
+            Error::MalformedStep => f.write_str("timestamp step has wrong number of successors"),
+            Error::OutputMismatch => f.write_str("timestamp step output does not match its input"),
+            Error::CommitmentMismatch => f.write_str("timestamp is for the wrong commitment"),
+            Error::NoAttestations => f.write_str("timestamp would have no attestations left"),
+            Error::IndexMismatch => f.write_str("archive index does not match its contents"),
+            Error::NoSuchEntry(n) => write!(f, "no archive entry {}", n),
+            Error::NoHeader(h) => write!(f, "no block header at height {}", h),
+            Error::MerkleRootMismatch(h) => write!(f, "commitment does not match merkle root of block {}", h),
+            Error::NoVerifiableAttestation => f.write_str("no attestations which can be verified"),
+            Error::BadAttestation(ref s) => write!(f, "bad attestation: {}", s),
+            Error::BadHeight(h) => write!(f, "block height {} is out of range", h),
+            Error::BrokenChain(h) => write!(f, "header at height {} does not connect to the chain", h),
+            Error::BadDifficulty(h) => write!(f, "header at height {} has wrong difficulty", h),
+            Error::BadProofOfWork(h) => write!(f, "header at height {} has insufficient proof of work", h),
+            Error::BadParams(s) => write!(f, "invalid consensus parameters: {}", s),
+            Error::BadCheckpoint(h) => write!(f, "checkpoint at height {} is not at a difficulty adjustment", h),
+            Error::Rpc(ref s) => write!(f, "header source: {}", s),
+            Error::Json(ref s) => write!(f, "malformed JSON timestamp: {}", s),
             Error::Utf8(ref e) => fmt::Display::fmt(e, f),
             Error::Io(ref e) => fmt::Display::fmt(e, f)
         }
@@ -77,27 +181,14 @@ impl fmt::Display for Error {
 }
 
 impl error::Error for Error {
-    fn description(&self) -> &str {
-        match *self {
-            Error::StackOverflow => "recursion limit reached",
-            Error::InvalidUriChar(_) => "invalid character in URI",
-            Error::BadDigestTag(_) => "invalid digest tag",
-            Error::BadOpTag(_) => "invalid op tag",
-            Error::BadMagic(_) => "bad magic bytes, is this a timestamp file?",
-            Error::BadVersion(_) => "timestamp version not understood",
-            Error::BadLength { .. } => "length out of bounds",
-            Error::TrailingBytes => "expected eof not",
-            Error::Utf8(ref e) => error::Error::description(e),
-            Error::Io(ref e) => error::Error::description(e)
-        }
-    }
-
-    fn cause(&self) -> Option<&error::Error> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
+            Error::AtOffset { ref error, .. } => Some(&**error),
+            Error::InStep { ref error, .. } => Some(&**error),
+            Error::InHeader(ref error) => Some(&**error),
             Error::Utf8(ref e) => Some(e),
             Error::Io(ref e) => Some(e),
             _ => None
         }
     }
 }
-
@@ -0,0 +1,267 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Header Chains
+//!
+//! A locally stored run of consecutive Bitcoin block headers, for verifying
+//! timestamps without any network access. The chain starts at some
+//! checkpoint height, whose header is trusted, and every later header must
+//! link to the one before it.
+//!
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use error::Error;
+use ser;
+use verify::{BlockHeader, BlockHeaderSource, HEADER_LEN};
+
+/// Magic bytes that every header file must start with
+const MAGIC: &[u8] = b"\x00OpenTimestamps\x00\x00Headers\x00\x5e\x1d\x0b\x6a\xc3\x94\x27\xf8";
+
+/// Major version of header files we understand
+const VERSION: usize = 1;
+
+/// Largest block height a chain may reach. Heights are 32-bit in Bitcoin's
+/// own data structures, so anything larger comes from a corrupt file.
+pub const MAX_HEIGHT: usize = 0xffff_ffff;
+
+/// A chain of consecutive block headers starting at a checkpoint
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HeaderChain {
+    start_height: usize,
+    headers: Vec<BlockHeader>
+}
+
+impl HeaderChain {
+    /// Constructs a chain consisting only of a trusted checkpoint header
+    pub fn new(start_height: usize, checkpoint: BlockHeader) -> HeaderChain {
+        HeaderChain {
+            start_height,
+            headers: vec![checkpoint]
+        }
+    }
+
+    /// Height of the checkpoint header
+    pub fn start_height(&self) -> usize {
+        self.start_height
+    }
+
+    /// Height of the last header in the chain
+    pub fn tip_height(&self) -> usize {
+        self.start_height + (self.headers.len() - 1)
+    }
+
+    /// The last header in the chain
+    pub fn tip(&self) -> &BlockHeader {
+        &self.headers[self.headers.len() - 1]
+    }
+
+    /// Looks up the header at the given height, if the chain covers it
+    pub fn header(&self, height: usize) -> Option<&BlockHeader> {
+        if height < self.start_height {
+            None
+        } else {
+            self.headers.get(height - self.start_height)
+        }
+    }
+
+    /// Adds a header to the tip of the chain, checking that it links to
+    /// the current tip
+    pub fn push(&mut self, header: BlockHeader) -> Result<(), Error> {
+        if self.tip_height() >= MAX_HEIGHT {
+            return Err(Error::BadHeight(self.tip_height()));
+        }
+        if header.prev_blockhash != self.tip().block_hash() {
+            return Err(Error::BrokenChain(self.tip_height() + 1));
+        }
+        self.headers.push(header);
+        Ok(())
+    }
+
+    /// Adds several headers to the tip of the chain. On error, headers up
+    /// to the offending one are kept.
+    pub fn extend<I: IntoIterator<Item = BlockHeader>>(&mut self, headers: I) -> Result<(), Error> {
+        for header in headers {
+            self.push(header)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a run of raw 80-byte headers, with no framing, as used by
+    /// Electrum's `blockchain_headers` file. The first header is taken
+    /// as the checkpoint, and must be at most `MAX_HEIGHT`.
+    pub fn from_raw_headers<R: Read>(start_height: usize, mut reader: R) -> Result<HeaderChain, Error> {
+        if start_height > MAX_HEIGHT {
+            return Err(Error::BadHeight(start_height));
+        }
+        let mut chain: Option<HeaderChain> = None;
+        let mut buf = [0; HEADER_LEN];
+        while read_header_or_eof(&mut reader, &mut buf)? {
+            let header = BlockHeader::from_bytes(&buf)?;
+            match chain {
+                Some(ref mut chain) => chain.push(header)?,
+                None => chain = Some(HeaderChain::new(start_height, header))
+            }
+        }
+        chain.ok_or(Error::NoHeader(start_height))
+    }
+
+    /// Deserialize a header file from a reader
+    pub fn from_reader<R: Read>(reader: R) -> Result<HeaderChain, Error> {
+        let mut deser = ser::Deserializer::new(reader);
+        let recv_magic = deser.read_fixed_bytes(MAGIC.len())?;
+        if recv_magic != MAGIC {
+            return Err(Error::BadMagic(recv_magic));
+        }
+        let recv_version = deser.read_uint()?;
+        if recv_version != VERSION {
+            return Err(Error::BadVersion(recv_version));
+        }
+        let start_height = deser.read_uint()?;
+        HeaderChain::from_raw_headers(start_height, deser.into_inner())
+    }
+
+    /// Serialize the chain into a writer
+    pub fn to_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut ser = ser::Serializer::new(writer);
+        ser.write_fixed_bytes(MAGIC)?;
+        ser.write_uint(VERSION)?;
+        ser.write_uint(self.start_height)?;
+        for header in &self.headers {
+            ser.write_fixed_bytes(&header.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Loads a header file from disk
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<HeaderChain, Error> {
+        let fh = fs::File::open(path)?;
+        HeaderChain::from_reader(io::BufReader::new(fh))
+    }
+
+    /// Saves the chain to disk atomically, so a crash never leaves a
+    /// truncated header file behind
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        ser::write_atomic(path, |writer| self.to_writer(writer))
+    }
+}
+
+impl BlockHeaderSource for HeaderChain {
+    fn block_header(&self, height: usize) -> Result<BlockHeader, Error> {
+        self.header(height).cloned().ok_or(Error::NoHeader(height))
+    }
+}
+
+/// Fills `buf` with the next header, returning false on a clean EOF
+fn read_header_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(Error::BadLength { min: HEADER_LEN, max: HEADER_LEN, val: filled }),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(Error::Io(e))
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain_of(n: usize) -> Vec<BlockHeader> {
+        let mut ret = vec![];
+        let mut prev = [0; 32];
+        for i in 0..n {
+            let header = BlockHeader {
+                version: 1,
+                prev_blockhash: prev,
+                merkle_root: [i as u8; 32],
+                time: 1500000000 + 600 * i as u32,
+                bits: 0x207fffff,
+                nonce: 0
+            };
+            prev = header.block_hash();
+            ret.push(header);
+        }
+        ret
+    }
+
+    #[test]
+    fn extend_and_lookup() {
+        let headers = chain_of(5);
+        let mut chain = HeaderChain::new(100, headers[0]);
+        chain.extend(headers[1..4].iter().cloned()).unwrap();
+        assert_eq!(chain.start_height(), 100);
+        assert_eq!(chain.tip_height(), 103);
+        assert_eq!(chain.block_header(102).unwrap(), headers[2]);
+        assert!(chain.header(99).is_none());
+        assert!(chain.header(104).is_none());
+
+        // Skipping a header breaks the chain
+        match chain.push(headers[1]) {
+            Err(Error::BrokenChain(104)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        chain.push(headers[4]).unwrap();
+        assert_eq!(chain.tip(), &headers[4]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let headers = chain_of(4);
+        let mut chain = HeaderChain::new(7, headers[0]);
+        chain.extend(headers[1..].iter().cloned()).unwrap();
+
+        let mut data = vec![];
+        chain.to_writer(&mut data).unwrap();
+        assert_eq!(HeaderChain::from_reader(&data[..]).unwrap(), chain);
+
+        // Truncated header
+        assert!(HeaderChain::from_reader(&data[..data.len() - 1]).is_err());
+
+        // Raw headers
+        let raw: Vec<u8> = headers.iter().flat_map(|h| h.to_bytes()).collect();
+        assert_eq!(HeaderChain::from_raw_headers(7, &raw[..]).unwrap(), chain);
+    }
+
+    #[test]
+    fn height_overflow() {
+        let headers = chain_of(2);
+        let raw: Vec<u8> = headers.iter().flat_map(|h| h.to_bytes()).collect();
+        match HeaderChain::from_raw_headers(usize::MAX, &raw[..]) {
+            Err(Error::BadHeight(h)) if h == usize::MAX => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // An untrusted file cannot claim a start height near the limit either
+        let mut chain = HeaderChain::new(usize::MAX, headers[0]);
+        let mut data = vec![];
+        chain.to_writer(&mut data).unwrap();
+        assert!(matches!(HeaderChain::from_reader(&data[..]), Err(Error::BadHeight(_))));
+
+        // Chains built directly cannot be pushed past the limit
+        assert!(matches!(chain.push(headers[1]), Err(Error::BadHeight(_))));
+        assert_eq!(chain.tip_height(), usize::MAX);
+
+        let mut chain = HeaderChain::new(MAX_HEIGHT - 1, headers[0]);
+        chain.push(headers[1]).unwrap();
+        assert_eq!(chain.tip_height(), MAX_HEIGHT);
+        assert!(HeaderChain::from_raw_headers(MAX_HEIGHT - 1, &raw[..]).is_ok());
+    }
+}
@@ -14,11 +14,13 @@
 
 //! # Hex
 //!
-//! Quick and dirty bytes-to-hex implementation
+//! Quick and dirty bytes-to-hex implementation, and the reverse
 //!
 
 use std::fmt::{self, Write};
 
+use error::Error;
+
 /// Wrapper around a byteslice that allows formatting as hex
 pub struct Hexed<'a>(pub &'a [u8]);
 
@@ -49,3 +51,138 @@ impl<'a> fmt::LowerHex for Hexed<'a> {
     }
 }
 
+
+impl<'a> Hexed<'a> {
+    /// Formatting options for more readable output of long data
+    pub fn options(self) -> HexOptions<'a> {
+        HexOptions {
+            data: self.0,
+            group: None,
+            max_len: None
+        }
+    }
+}
+
+/// Hex formatting with byte grouping and truncation, from `Hexed::options`
+#[derive(Copy, Clone, Debug)]
+pub struct HexOptions<'a> {
+    data: &'a [u8],
+    group: Option<(usize, char)>,
+    max_len: Option<usize>
+}
+
+impl<'a> HexOptions<'a> {
+    /// Splits the output into groups of `size` bytes, with `separator`
+    /// between them
+    pub fn group(mut self, size: usize, separator: char) -> HexOptions<'a> {
+        self.group = if size > 0 { Some((size, separator)) } else { None };
+        self
+    }
+
+    /// Shows at most `max_len` bytes, replacing the middle of longer
+    /// data by `..`
+    pub fn truncate(mut self, max_len: usize) -> HexOptions<'a> {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    fn fmt_part(&self, f: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+        match self.group {
+            Some((size, separator)) => {
+                for (i, chunk) in data.chunks(size).enumerate() {
+                    if i > 0 {
+                        f.write_char(separator)?;
+                    }
+                    fmt::Debug::fmt(&Hexed(chunk), f)?;
+                }
+                Ok(())
+            }
+            None => fmt::Debug::fmt(&Hexed(data), f)
+        }
+    }
+}
+
+impl<'a> fmt::Display for HexOptions<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max_len {
+            Some(max_len) if self.data.len() > max_len => {
+                let head = (max_len + 1) / 2;
+                self.fmt_part(f, &self.data[..head])?;
+                f.write_str("..")?;
+                self.fmt_part(f, &self.data[self.data.len() - (max_len - head)..])
+            }
+            _ => self.fmt_part(f, self.data)
+        }
+    }
+}
+
+/// Decodes a hex string, in either case, into bytes
+pub fn decode(s: &str) -> Result<Vec<u8>, Error> {
+    let mut ret = Vec::with_capacity(s.len() / 2);
+    let mut high = None;
+    for c in s.chars() {
+        let nibble = match c.to_digit(16) {
+            Some(n) => n as u8,
+            None => return Err(Error::InvalidHexChar(c))
+        };
+        match high.take() {
+            Some(high) => ret.push(high << 4 | nibble),
+            None => high = Some(nibble)
+        }
+    }
+    if high.is_some() {
+        return Err(Error::OddHexLength(s.chars().count()));
+    }
+    Ok(ret)
+}
+
+/// Decodes a hex string into a fixed number of bytes, e.g. a digest
+pub fn decode_array<const N: usize>(s: &str) -> Result<[u8; N], Error> {
+    let data = decode(s)?;
+    if data.len() != N {
+        return Err(Error::BadLength { min: N, max: N, val: data.len() });
+    }
+    let mut ret = [0; N];
+    ret.copy_from_slice(&data);
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let hex = format!("{}", Hexed(&data));
+        assert_eq!(decode(&hex).unwrap(), data);
+        assert_eq!(decode(&hex.to_uppercase()).unwrap(), data);
+        assert!(decode("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn options() {
+        let data: Vec<u8> = (0..10).collect();
+        assert_eq!(format!("{}", Hexed(&data).options()), "00010203040506070809");
+        assert_eq!(format!("{}", Hexed(&data).options().group(4, ' ')), "00010203 04050607 0809");
+        assert_eq!(format!("{}", Hexed(&data).options().truncate(10)), "00010203040506070809");
+        assert_eq!(format!("{}", Hexed(&data).options().truncate(5)), "000102..0809");
+        assert_eq!(format!("{}", Hexed(&data).options().truncate(4).group(1, ':')), "00:01..08:09");
+        assert_eq!(format!("{}", Hexed(&data).options().truncate(0)), "..");
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(matches!(decode("abc"), Err(Error::OddHexLength(3))));
+        assert!(matches!(decode("0g"), Err(Error::InvalidHexChar('g'))));
+        assert!(matches!(decode("+1"), Err(Error::InvalidHexChar('+'))));
+        assert!(matches!(decode("\u{e9}0"), Err(Error::InvalidHexChar('\u{e9}'))));
+    }
+
+    #[test]
+    fn decode_array() {
+        assert_eq!(super::decode_array::<2>("beef").unwrap(), [0xbe, 0xef]);
+        assert!(matches!(super::decode_array::<2>("be"), Err(Error::BadLength { min: 2, max: 2, val: 1 })));
+        assert!(matches!(super::decode_array::<32>(""), Err(Error::BadLength { min: 32, max: 32, val: 0 })));
+    }
+}
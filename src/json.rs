@@ -0,0 +1,415 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # JSON
+//!
+//! A JSON representation of timestamps, as an alternative to the binary
+//! format for web APIs and inspection with standard tools. Byte strings
+//! are lowercase hex. A timestamp looks like
+//!
+//! ```text
+//! { "start_digest": "a70d...",
+//!   "first_step": { "op": "append", "arg": "7a46...", "output": "2d1e...",
+//!                   "next": { "fork": [ ..., { "attestation": { "bitcoin": 449397 } } ] } } }
+//! ```
+//!
+//! Op outputs are included for readability. On import they are
+//! recomputed, and if present must match.
+//!
+//! With the `serde` feature, which implies this one, the same
+//! representation is used to implement `Serialize` and `Deserialize`.
+//! Deserialization goes through `serde_json::Value`, so it only works
+//! with self-describing formats such as JSON, CBOR or YAML, and not with
+//! e.g. bincode or postcard. `DigestType` is the exception, being a bare
+//! string. `Step` is serialize-only, since its outputs cannot be computed
+//! without knowing its input.
+//!
+
+use std::convert::TryFrom;
+
+#[cfg(feature = "serde")]
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use attestation::{self, Attestation};
+use error::Error;
+use hex::{self, Hexed};
+use op::{Op, MAX_OP_LENGTH, MAX_RESULT_LENGTH};
+use ser::{DetachedTimestampFile, DigestType};
+use timestamp::{Step, StepData, Timestamp, RECURSION_LIMIT};
+
+fn bad(msg: &str) -> Error {
+    Error::Json(msg.to_owned())
+}
+
+fn hex(data: &[u8]) -> Value {
+    Value::String(format!("{}", Hexed(data)))
+}
+
+fn get<'a>(obj: &'a Value, key: &str) -> Result<&'a Value, Error> {
+    obj.get(key).ok_or_else(|| Error::Json(format!("missing field `{}`", key)))
+}
+
+fn get_hex(obj: &Value, key: &str) -> Result<Vec<u8>, Error> {
+    match *get(obj, key)? {
+        Value::String(ref s) => hex::decode(s).map_err(|e| Error::Json(format!("field `{}` is not valid hex: {}", key, e))),
+        _ => Err(Error::Json(format!("field `{}` is not a string", key)))
+    }
+}
+
+/// A block height, which must fit in a `usize` on this platform
+fn get_height(height: &Value, chain: &str) -> Result<usize, Error> {
+    match height.as_u64().map(usize::try_from) {
+        Some(Ok(height)) => Ok(height),
+        Some(Err(_)) => Err(Error::Json(format!("{} height is too large", chain))),
+        None => Err(Error::Json(format!("{} height is not an integer", chain)))
+    }
+}
+
+/// The only key of a single-key object, e.g. `{"bitcoin": 1}`
+fn single_key(obj: &Value) -> Result<(&str, &Value), Error> {
+    match *obj {
+        Value::Object(ref map) if map.len() == 1 => {
+            let (key, val) = map.iter().next().expect("len 1");
+            Ok((key, val))
+        }
+        _ => Err(bad("expected an object with a single key"))
+    }
+}
+
+impl Op {
+    /// Converts the op to JSON, an object with an `op` name and, for
+    /// binary ops, a hex `arg`
+    pub fn to_json(&self) -> Value {
+        let mut map = Map::new();
+        let (name, arg) = match *self {
+            Op::Sha1 => ("sha1", None),
+            Op::Sha256 => ("sha256", None),
+            Op::Ripemd160 => ("ripemd160", None),
+            Op::Keccak256 => ("keccak256", None),
+            Op::Hexlify => ("hexlify", None),
+            Op::Reverse => ("reverse", None),
+            Op::Append(ref data) => ("append", Some(data)),
+            Op::Prepend(ref data) => ("prepend", Some(data))
+        };
+        map.insert("op".to_owned(), Value::String(name.to_owned()));
+        if let Some(arg) = arg {
+            map.insert("arg".to_owned(), hex(arg));
+        }
+        Value::Object(map)
+    }
+
+    /// Reads an op from JSON; other fields of the object are ignored
+    pub fn from_json(json: &Value) -> Result<Op, Error> {
+        let arg = || -> Result<Vec<u8>, Error> {
+            let arg = get_hex(json, "arg")?;
+            if arg.is_empty() || arg.len() > MAX_OP_LENGTH {
+                return Err(Error::BadLength { min: 1, max: MAX_OP_LENGTH, val: arg.len() });
+            }
+            Ok(arg)
+        };
+        match get(json, "op")?.as_str() {
+            Some("sha1") => Ok(Op::Sha1),
+            Some("sha256") => Ok(Op::Sha256),
+            Some("ripemd160") => Ok(Op::Ripemd160),
+            Some("keccak256") => Ok(Op::Keccak256),
+            Some("hexlify") => Ok(Op::Hexlify),
+            Some("reverse") => Ok(Op::Reverse),
+            Some("append") => Ok(Op::Append(arg()?)),
+            Some("prepend") => Ok(Op::Prepend(arg()?)),
+            Some(name) => Err(Error::Json(format!("unknown op {}", name))),
+            None => Err(bad("field `op` is not a string"))
+        }
+    }
+}
+
+impl Attestation {
+    /// Converts the attestation to JSON: `{"bitcoin": <height>}`,
+    /// `{"ethereum": <height>}`, `{"pending": <uri>}` or `{"unknown": {"tag": <hex>, "data": <hex>}}`
+    pub fn to_json(&self) -> Value {
+        match *self {
+            Attestation::Bitcoin { height } => json!({ "bitcoin": height }),
+            Attestation::Pending { ref uri } => json!({ "pending": uri }),
+            Attestation::Ethereum { height } => json!({ "ethereum": height }),
+            Attestation::Unknown { ref tag, ref data } => json!({ "unknown": { "tag": hex(tag), "data": hex(data) } })
+        }
+    }
+
+    /// Reads an attestation from JSON
+    pub fn from_json(json: &Value) -> Result<Attestation, Error> {
+        match single_key(json)? {
+            ("bitcoin", height) => Ok(Attestation::Bitcoin { height: get_height(height, "bitcoin")? }),
+            ("pending", uri) => match uri.as_str() {
+                Some(uri) => {
+                    attestation::validate_uri(uri)?;
+                    Ok(Attestation::Pending { uri: uri.to_owned() })
+                }
+                None => Err(bad("pending uri is not a string"))
+            },
+            ("ethereum", height) => Ok(Attestation::Ethereum { height: get_height(height, "ethereum")? }),
+            ("unknown", unknown) => {
+                let tag = get_hex(unknown, "tag")?;
+                if tag.len() != attestation::TAG_SIZE {
+                    return Err(Error::BadLength { min: attestation::TAG_SIZE, max: attestation::TAG_SIZE, val: tag.len() });
+                }
+                Ok(Attestation::Unknown { tag, data: get_hex(unknown, "data")? })
+            }
+            (name, _) => Err(Error::Json(format!("unknown attestation type {}", name)))
+        }
+    }
+}
+
+impl Step {
+    /// Converts the step and its successors to JSON. A fork is
+    /// `{"fork": [<step>...]}`, an attestation `{"attestation": <attestation>}`,
+    /// and an op is the op's object with `output` and `next` fields added.
+    pub fn to_json(&self) -> Value {
+        match self.data {
+            StepData::Fork => {
+                json!({ "fork": self.next.iter().map(Step::to_json).collect::<Vec<_>>() })
+            }
+            StepData::Op(ref op) => {
+                let mut ret = op.to_json();
+                ret["output"] = hex(&self.output);
+                ret["next"] = self.next[0].to_json();
+                ret
+            }
+            StepData::Attestation(ref attest) => json!({ "attestation": attest.to_json() })
+        }
+    }
+
+    /// Reads a step from JSON, executing ops starting from `input`
+    pub fn from_json(json: &Value, input: &[u8]) -> Result<Step, Error> {
+        Step::from_json_recurse(json, input, RECURSION_LIMIT)
+    }
+
+    fn from_json_recurse(json: &Value, input: &[u8], recursion_limit: usize) -> Result<Step, Error> {
+        if recursion_limit == 0 {
+            return Err(Error::StackOverflow);
+        }
+
+        if let Some(forks) = json.get("fork") {
+            let forks = match forks.as_array() {
+                Some(forks) if forks.len() >= 2 => forks,
+                _ => return Err(bad("fork is not an array of at least two steps"))
+            };
+            let next = forks.iter()
+                            .map(|fork| Step::from_json_recurse(fork, input, recursion_limit - 1))
+                            .collect::<Result<Vec<_>, _>>()?;
+            Ok(Step { data: StepData::Fork, output: input.to_vec(), next })
+        } else if let Some(attest) = json.get("attestation") {
+            Ok(Step {
+                data: StepData::Attestation(Attestation::from_json(attest)?),
+                output: input.to_vec(),
+                next: vec![]
+            })
+        } else {
+            let op = Op::from_json(json)?;
+            let output = op.execute(input);
+            if output.len() > MAX_RESULT_LENGTH {
+                return Err(Error::BadLength { min: 0, max: MAX_RESULT_LENGTH, val: output.len() });
+            }
+            if json.get("output").is_some() && get_hex(json, "output")? != output {
+                return Err(Error::OutputMismatch);
+            }
+            let next = Step::from_json_recurse(get(json, "next")?, &output, recursion_limit - 1)?;
+            Ok(Step { data: StepData::Op(op), output, next: vec![next] })
+        }
+    }
+}
+
+impl Timestamp {
+    /// Converts the timestamp to JSON
+    pub fn to_json(&self) -> Value {
+        json!({ "start_digest": hex(&self.start_digest), "first_step": self.first_step.to_json() })
+    }
+
+    /// Reads a timestamp from JSON
+    pub fn from_json(json: &Value) -> Result<Timestamp, Error> {
+        let start_digest = get_hex(json, "start_digest")?;
+        let first_step = Step::from_json(get(json, "first_step")?, &start_digest)?;
+        Ok(Timestamp { start_digest, first_step })
+    }
+}
+
+impl DigestType {
+    /// Converts the digest type to JSON, as the lowercase name of the hash
+    pub fn to_json(&self) -> Value {
+        Value::String(self.name().to_owned())
+    }
+
+    /// Reads a digest type from JSON
+    pub fn from_json(json: &Value) -> Result<DigestType, Error> {
+        match json.as_str() {
+            Some(name) => DigestType::from_name(name),
+            None => Err(bad("digest type is not a string"))
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            DigestType::Sha1 => "sha1",
+            DigestType::Sha256 => "sha256",
+            DigestType::Ripemd160 => "ripemd160",
+            DigestType::Keccak256 => "keccak256"
+        }
+    }
+
+    fn from_name(name: &str) -> Result<DigestType, Error> {
+        match name {
+            "sha1" => Ok(DigestType::Sha1),
+            "sha256" => Ok(DigestType::Sha256),
+            "ripemd160" => Ok(DigestType::Ripemd160),
+            "keccak256" => Ok(DigestType::Keccak256),
+            _ => Err(Error::Json(format!("unknown digest type {}", name)))
+        }
+    }
+}
+
+impl DetachedTimestampFile {
+    /// Converts the file to JSON, adding a `digest_type` field naming
+    /// the hash used on the timestamped data
+    pub fn to_json(&self) -> Value {
+        json!({ "digest_type": self.digest_type.to_json(), "timestamp": self.timestamp.to_json() })
+    }
+
+    /// Reads a file from JSON
+    pub fn from_json(json: &Value) -> Result<DetachedTimestampFile, Error> {
+        let digest_type = DigestType::from_json(get(json, "digest_type")?)?;
+        let timestamp = Timestamp::from_json(get(json, "timestamp")?)?;
+        if timestamp.start_digest.len() != digest_type.digest_len() {
+            return Err(Error::BadLength {
+                min: digest_type.digest_len(),
+                max: digest_type.digest_len(),
+                val: timestamp.start_digest.len()
+            });
+        }
+        Ok(DetachedTimestampFile { digest_type, timestamp })
+    }
+}
+
+#[cfg(feature = "serde")]
+macro_rules! impl_serde {
+    ($ty:ident) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                self.to_json().serialize(s)
+            }
+        }
+    };
+    ($ty:ident, deserialize) => {
+        impl_serde!($ty);
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(d: D) -> Result<$ty, D::Error> {
+                let json = Value::deserialize(d)?;
+                $ty::from_json(&json).map_err(de::Error::custom)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")] impl_serde!(Op, deserialize);
+#[cfg(feature = "serde")] impl_serde!(Attestation, deserialize);
+#[cfg(feature = "serde")] impl_serde!(Step);
+#[cfg(feature = "serde")] impl_serde!(Timestamp, deserialize);
+#[cfg(feature = "serde")] impl_serde!(DetachedTimestampFile, deserialize);
+
+// A bare string, so unlike the above this works in any serde format
+#[cfg(feature = "serde")]
+impl Serialize for DigestType {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for DigestType {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<DigestType, D::Error> {
+        let name = String::deserialize(d)?;
+        DigestType::from_name(&name).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use tests::{LARGE_TEST, SMALL_TEST};
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for data in &[SMALL_TEST, LARGE_TEST] {
+            let ots = DetachedTimestampFile::from_reader(*data).unwrap();
+            let json = ots.to_json();
+            assert_eq!(DetachedTimestampFile::from_json(&json).unwrap(), ots);
+            let text = serde_json::to_string(&json).unwrap();
+            assert_eq!(DetachedTimestampFile::from_json(&serde_json::from_str(&text).unwrap()).unwrap(), ots);
+        }
+    }
+
+    #[test]
+    fn format() {
+        let json = json!({
+            "start_digest": "00",
+            "first_step": { "fork": [
+                { "attestation": { "bitcoin": 10 } },
+                { "op": "append", "arg": "ff", "next": { "attestation": { "pending": "https://example.com" } } }
+            ] }
+        });
+        let ts = Timestamp::from_json(&json).unwrap();
+        assert_eq!(ts.first_step.next[1].output, vec![0x00, 0xff]);
+        assert_eq!(ts.to_json()["first_step"]["fork"][1]["output"], "00ff");
+
+        let mut bad = json.clone();
+        bad["first_step"]["fork"][1]["output"] = json!("0000");
+        match Timestamp::from_json(&bad) {
+            Err(Error::OutputMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        let mut bad = json.clone();
+        bad["first_step"]["fork"][1]["next"]["attestation"]["pending"] = json!("https://evil.com/?");
+        assert!(Timestamp::from_json(&bad).is_err());
+
+        let mut bad = json.clone();
+        bad["first_step"]["fork"][1]["op"] = json!("sha3");
+        assert!(Timestamp::from_json(&bad).is_err());
+
+        let mut bad = json.clone();
+        bad["first_step"]["fork"][1]["arg"] = json!("fg");
+        match Timestamp::from_json(&bad) {
+            Err(Error::Json(ref msg)) if msg.contains("`arg`") => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let text = serde_json::to_string(&ots).unwrap();
+        assert_eq!(serde_json::from_str::<DetachedTimestampFile>(&text).unwrap(), ots);
+        assert_eq!(serde_json::to_value(&ots.timestamp.first_step).unwrap(), ots.timestamp.first_step.to_json());
+
+        let op: Op = serde_json::from_str("{\"op\":\"append\",\"arg\":\"abcd\"}").unwrap();
+        assert_eq!(op, Op::Append(vec![0xab, 0xcd]));
+        assert!(serde_json::from_str::<Attestation>("{\"pending\":\"a b\"}").is_err());
+
+        assert_eq!(serde_json::to_string(&DigestType::Ripemd160).unwrap(), "\"ripemd160\"");
+        assert_eq!(serde_json::from_str::<DigestType>("\"keccak256\"").unwrap(), DigestType::Keccak256);
+        assert!(serde_json::from_str::<DigestType>("\"md5\"").is_err());
+    }
+}
@@ -28,13 +28,29 @@
 
 extern crate crypto;
 #[macro_use] extern crate log;
+#[cfg(feature = "serde")] extern crate serde;
+#[cfg(feature = "serde_json")] #[macro_use] extern crate serde_json;
+#[cfg(feature = "sha2")] extern crate sha2;
 
+pub mod archive;
+pub mod armor;
 pub mod attestation;
+mod base64;
+#[cfg(feature = "bitcoind")]
+pub mod bitcoind;
+pub mod calendar;
 pub mod error;
+pub mod headers;
 pub mod hex;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod op;
 pub mod timestamp;
 pub mod ser;
+pub mod store;
+pub mod stream;
+pub mod spv;
+pub mod verify;
 
 pub use ser::DetachedTimestampFile;
 pub use timestamp::Timestamp;
@@ -43,7 +59,7 @@ pub use timestamp::Timestamp;
 mod tests {
     use super::*;
 
-    const SMALL_TEST: &'static [u8] = b"\
+    pub const SMALL_TEST: &[u8] = b"\
 \x00\x4f\x70\x65\x6e\x54\x69\x6d\x65\x73\x74\x61\x6d\x70\x73\x00\x00\x50\x72\x6f\x6f\x66\x00\xbf\x89\xe2\xe8\x84\xe8\x92\
 \x94\x01\x08\xa7\x0d\xfe\x69\xc5\xa0\xd6\x28\x16\x78\x1a\xbb\x6e\x17\x77\x85\x47\x18\x62\x4a\x0d\x19\x42\x31\xad\xb1\x4c\
 \x32\xee\x54\x38\xa4\xf0\x10\x7a\x46\x05\xde\x0a\x5b\x37\xcb\x21\x17\x59\xc6\x81\x2b\xfe\x2e\x08\xff\xf0\x10\x24\x4b\x79\
@@ -54,7 +70,7 @@ mod tests {
 \x83\xdf\xe3\x0d\x2e\xf9\x0c\x8e\x2e\x2d\x68\x74\x74\x70\x73\x3a\x2f\x2f\x61\x6c\x69\x63\x65\x2e\x62\x74\x63\x2e\x63\x61\
 \x6c\x65\x6e\x64\x61\x72\x2e\x6f\x70\x65\x6e\x74\x69\x6d\x65\x73\x74\x61\x6d\x70\x73\x2e\x6f\x72\x67";
 
-    const LARGE_TEST: &'static [u8] = b"\
+    pub const LARGE_TEST: &[u8] = b"\
 \x00\x4f\x70\x65\x6e\x54\x69\x6d\x65\x73\x74\x61\x6d\x70\x73\x00\x00\x50\x72\x6f\x6f\x66\x00\xbf\x89\xe2\xe8\x84\xe8\x92\
 \x94\x01\x08\x6f\xd9\xc1\xc4\xf0\x96\xb7\x7e\x6d\x44\x57\xba\xc1\xc7\xf5\x10\x10\xd3\x18\xdb\x48\x3f\x28\x68\xd3\x79\x58\
 \x43\xf0\x98\xd3\x78\xf0\x10\xe2\xe2\x24\x43\x9e\x7f\x0f\xdd\x8c\x1e\xea\xc7\x3e\xa7\x39\xdb\x08\xf1\x20\xa5\x74\x44\x4a\
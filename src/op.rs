@@ -23,8 +23,10 @@
 
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
-use crypto::sha2::Sha256;
+#[cfg(not(feature = "sha2"))]
+pub(crate) use crypto::sha2::Sha256;
 use crypto::ripemd160::Ripemd160;
+use crypto::sha3::Sha3;
 use std::fmt;
 use std::io::{Read, Write};
 
@@ -32,8 +34,45 @@ use error::Error;
 use hex::Hexed;
 use ser;
 
-/// Maximum length of an op result
-const MAX_OP_LENGTH: usize = 4096;
+/// Maximum length of the argument of an append or prepend op
+pub const MAX_OP_LENGTH: usize = 4096;
+/// Maximum length of an op result, as in python-opentimestamps
+pub const MAX_RESULT_LENGTH: usize = 4096;
+
+/// SHA256 using the `sha2` crate, which uses the CPU's SHA extensions
+/// where it has them, behind rust-crypto's `Digest` interface
+#[cfg(feature = "sha2")]
+pub(crate) struct Sha256(::sha2::Sha256);
+
+#[cfg(feature = "sha2")]
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256(::sha2::Digest::new())
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl Digest for Sha256 {
+    fn input(&mut self, input: &[u8]) {
+        ::sha2::Digest::update(&mut self.0, input);
+    }
+
+    fn result(&mut self, out: &mut [u8]) {
+        out.copy_from_slice(&::sha2::Digest::finalize_reset(&mut self.0));
+    }
+
+    fn reset(&mut self) {
+        ::sha2::Digest::reset(&mut self.0);
+    }
+
+    fn output_bits(&self) -> usize {
+        256
+    }
+
+    fn block_size(&self) -> usize {
+        64
+    }
+}
 
 /// All the types of operations supported
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -43,6 +82,7 @@ pub enum Op {
     Sha1,
     Sha256,
     Ripemd160,
+    Keccak256,
     // unary ops
     Hexlify,
     Reverse,
@@ -58,6 +98,7 @@ impl Op {
             Op::Sha1 => 0x02,
             Op::Sha256 => 0x08,
             Op::Ripemd160 => 0x03,
+            Op::Keccak256 => 0x67,
             Op::Hexlify => 0xf3,
             Op::Reverse => 0xf2,
             Op::Append(_) => 0xf0,
@@ -78,6 +119,7 @@ impl Op {
             0x02 => Ok(Op::Sha1),
             0x08 => Ok(Op::Sha256),
             0x03 => Ok(Op::Ripemd160),
+            0x67 => Ok(Op::Keccak256),
             0xf3 => Ok(Op::Hexlify),
             0xf2 => Ok(Op::Reverse),
             // binary ops are almost trivial
@@ -101,43 +143,54 @@ impl Op {
 
     /// Execute an op on the given data
     pub fn execute(&self, input: &[u8]) -> Vec<u8> {
+        let mut ret = vec![];
+        self.execute_into(input, &mut ret);
+        ret
+    }
+
+    /// Execute an op on the given data, replacing the contents of `output`.
+    /// Reusing one output buffer avoids an allocation per op when replaying
+    /// long chains of ops.
+    pub fn execute_into(&self, input: &[u8], output: &mut Vec<u8>) {
+        output.clear();
         match *self {
             Op::Sha1 => {
-                let mut ret = vec![0; 20];
+                output.resize(20, 0);
                 let mut hasher = Sha1::new();
                 hasher.input(input);
-                hasher.result(&mut ret);
-                ret
+                hasher.result(output);
             }
             Op::Sha256 => {
-                let mut ret = vec![0; 32];
+                output.resize(32, 0);
                 let mut hasher = Sha256::new();
                 hasher.input(input);
-                hasher.result(&mut ret);
-                ret
+                hasher.result(output);
             }
             Op::Ripemd160 => {
-                let mut ret = vec![0; 20];
+                output.resize(20, 0);
                 let mut hasher = Ripemd160::new();
                 hasher.input(input);
-                hasher.result(&mut ret);
-                ret
+                hasher.result(output);
+            }
+            Op::Keccak256 => {
+                output.resize(32, 0);
+                let mut hasher = Sha3::keccak256();
+                hasher.input(input);
+                hasher.result(output);
             }
             Op::Hexlify => {
-                format!("{}", Hexed(input)).into_bytes()
+                write!(output, "{}", Hexed(input)).expect("writing to a vec never fails");
             }
             Op::Reverse => {
-                input.iter().map(|x| *x).rev().collect()
+                output.extend(input.iter().rev());
             }
             Op::Append(ref data) => {
-                let mut vec = input.to_vec();
-                vec.extend(data);
-                vec
+                output.extend_from_slice(input);
+                output.extend_from_slice(data);
             }
             Op::Prepend(ref data) => {
-                let mut vec = data.to_vec();
-                vec.extend(input);
-                vec
+                output.extend_from_slice(data);
+                output.extend_from_slice(input);
             }
         }
     }
@@ -148,7 +201,8 @@ impl fmt::Display for Op {
         match *self {
             Op::Sha1 => f.write_str("SHA1()"),
             Op::Sha256 => f.write_str("SHA256()"),
-            Op::Ripemd160 => f.write_str("RIPEMD16()"),
+            Op::Ripemd160 => f.write_str("RIPEMD160()"),
+            Op::Keccak256 => f.write_str("KECCAK256()"),
             Op::Hexlify => f.write_str("Hexlify()"),
             Op::Reverse => f.write_str("Reverse()"),
             Op::Append(ref data) => write!(f, "Append({})", Hexed(data)),
@@ -157,4 +211,58 @@ impl fmt::Display for Op {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn keccak256() {
+        // Ethereum's hash of the empty string, not SHA3-256's
+        assert_eq!(format!("{}", Hexed(&Op::Keccak256.execute(b""))),
+                   "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+
+        let mut ser = ser::Serializer::new(vec![]);
+        Op::Keccak256.serialize(&mut ser).unwrap();
+        let data = ser.into_inner();
+        assert_eq!(data, vec![0x67]);
+        let mut deser = ser::Deserializer::new(&data[..]);
+        assert_eq!(Op::deserialize(&mut deser).unwrap(), Op::Keccak256);
+    }
+
+    #[test]
+    fn execute_into() {
+        let ops = [Op::Sha1, Op::Sha256, Op::Ripemd160, Op::Keccak256, Op::Hexlify, Op::Reverse,
+                   Op::Append(vec![1, 2, 3]), Op::Prepend(vec![4, 5])];
+        // Start with a buffer longer than any output, to check it is cleared
+        let mut output = vec![0xaa; 100];
+        for op in &ops {
+            op.execute_into(b"input", &mut output);
+            assert_eq!(output, op.execute(b"input"));
+        }
+    }
+
+    #[test]
+    fn sha256() {
+        assert_eq!(format!("{}", Hexed(&Op::Sha256.execute(b""))),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        let mut hasher = Sha256::new();
+        hasher.input(b"ab");
+        hasher.input(b"c");
+        let mut out = [0; 32];
+        hasher.result(&mut out);
+        assert_eq!(format!("{}", Hexed(&out)),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn reverse() {
+        assert_eq!(Op::Reverse.execute(b"\x01\x02\x03"), b"\x03\x02\x01");
+        assert_eq!(Op::Reverse.execute(b""), b"");
+
+        let mut deser = ser::Deserializer::new(&b"\xf2"[..]);
+        assert_eq!(Op::deserialize(&mut deser).unwrap(), Op::Reverse);
+        let mut ser = ser::Serializer::new(vec![]);
+        Op::Reverse.serialize(&mut ser).unwrap();
+        assert_eq!(ser.into_inner(), b"\xf2");
+    }
+}
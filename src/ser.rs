@@ -17,19 +17,29 @@
 //! Supports deserialization and serialization of OTS info files
 //!
 
-use std::fmt;
-use std::io::{Read, Write};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use crypto::ripemd160::Ripemd160;
+use crypto::sha3::Sha3;
+use std::{fmt, fs, process};
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use error::Error;
 use hex::Hexed;
+use op::{Sha256, MAX_RESULT_LENGTH};
 use timestamp::Timestamp;
 
 /// Magic bytes that every proof must start with
-const MAGIC: &'static [u8] = b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
+const MAGIC: &[u8] = b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
 
 /// Major version of timestamp files we understand
 const VERSION: usize = 1;
 
+/// File extension of timestamp files
+pub const EXTENSION: &str = "ots";
+
 /// Structure representing an info file
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct DetachedTimestampFile {
@@ -40,11 +50,101 @@ pub struct DetachedTimestampFile {
 }
 
 impl DetachedTimestampFile {
-    /// Deserialize a info file from a reader
+    /// Deserialize a info file from a reader. Errors are wrapped in
+    /// context, so use `Error::root()` to match on them.
     pub fn from_reader<R: Read>(reader: R) -> Result<DetachedTimestampFile, Error> {
+        DetachedTimestampFile::from_reader_with_limits(reader, Limits::default())
+    }
+
+    /// Deserialize a info file from a reader, with non-default limits
+    /// on its size. Errors are wrapped in `Error::AtOffset`, giving the
+    /// position in the file of the item which failed to parse.
+    pub fn from_reader_with_limits<R: Read>(reader: R, limits: Limits) -> Result<DetachedTimestampFile, Error> {
+        let mut deser = Deserializer::with_limits(reader, limits);
+        let ret = match DetachedTimestampFile::deserialize(&mut deser) {
+            Ok(ots) => deser.check_eof().map(|_| ots),
+            Err(e) => Err(e)
+        };
+        ret.map_err(|e| Error::AtOffset {
+            offset: deser.item_start,
+            error: Box::new(e)
+        })
+    }
+
+    /// Deserialize a info file from the start of a reader, e.g. one
+    /// embedded in some larger container, returning it along with the
+    /// number of bytes it took up. Unlike `from_reader`, anything after
+    /// the proof is left unread rather than being an error.
+    pub fn from_reader_prefix<R: Read>(reader: R) -> Result<(DetachedTimestampFile, usize), Error> {
+        DetachedTimestampFile::from_reader_prefix_with_limits(reader, Limits::default())
+    }
+
+    /// Deserialize a info file from the start of a reader, as
+    /// `from_reader_prefix`, with non-default limits on its size
+    pub fn from_reader_prefix_with_limits<R: Read>(reader: R, limits: Limits) -> Result<(DetachedTimestampFile, usize), Error> {
+        let mut deser = Deserializer::with_limits(reader, limits);
+        match DetachedTimestampFile::deserialize(&mut deser) {
+            Ok(ots) => Ok((ots, deser.position())),
+            Err(e) => Err(Error::AtOffset {
+                offset: deser.item_start,
+                error: Box::new(e)
+            })
+        }
+    }
+
+    /// Deserialize any number of info files written back to back, as
+    /// produced by `to_writer_all`
+    pub fn from_reader_all<R: Read>(reader: R) -> Result<Vec<DetachedTimestampFile>, Error> {
+        let mut reader = io::BufReader::new(reader);
+        let mut ret = vec![];
+        let mut total = 0;
+        loop {
+            match reader.fill_buf() {
+                Ok(&[]) => break,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::AtOffset {
+                    offset: total,
+                    error: Box::new(Error::Io(e))
+                })
+            }
+            match DetachedTimestampFile::from_reader_prefix(&mut reader) {
+                Ok((ots, len)) => {
+                    ret.push(ots);
+                    total += len;
+                }
+                Err(Error::AtOffset { offset, error }) => return Err(Error::AtOffset {
+                    offset: total + offset,
+                    error
+                }),
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Serialize several info files back to back into a writer
+    pub fn to_writer_all<W: Write>(files: &[DetachedTimestampFile], mut writer: W) -> Result<(), Error> {
+        for ots in files {
+            ots.to_writer(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(deser: &mut Deserializer<R>) -> Result<DetachedTimestampFile, Error> {
         trace!("Start deserializing timestampfile from reader.");
-        let mut deser = Deserializer::new(reader);
 
+        let (digest_type, digest) = DetachedTimestampFile::deserialize_header(deser)
+            .map_err(|e| Error::InHeader(Box::new(e)))?;
+        let timestamp = Timestamp::deserialize(deser, digest)?;
+
+        Ok(DetachedTimestampFile {
+            digest_type,
+            timestamp
+        })
+    }
+
+    fn deserialize_header<R: Read>(deser: &mut Deserializer<R>) -> Result<(DigestType, Vec<u8>), Error> {
         deser.read_magic()?;
         trace!("Magic ok.");
         deser.read_version()?;
@@ -53,14 +153,7 @@ impl DetachedTimestampFile {
         trace!("Digest type: {}", digest_type);
         let digest = deser.read_fixed_bytes(digest_type.digest_len())?;
         trace!("Digest: {}", Hexed(&digest));
-        let timestamp = Timestamp::deserialize(&mut deser, digest)?;
-
-        deser.check_eof()?;
-
-        Ok(DetachedTimestampFile {
-            digest_type: digest_type,
-            timestamp: timestamp
-        })
+        Ok((digest_type, digest))
     }
 
     /// Serialize the file into a reader
@@ -75,6 +168,83 @@ impl DetachedTimestampFile {
         ser.write_fixed_bytes(&self.timestamp.start_digest)?;
         self.timestamp.serialize(&mut ser)
     }
+
+    /// Checks that the data read from a reader is what was timestamped,
+    /// by hashing it and comparing against the start digest
+    pub fn check_reader<R: Read>(&self, reader: R) -> Result<(), Error> {
+        if self.digest_type.digest_reader(reader)? == self.timestamp.start_digest {
+            Ok(())
+        } else {
+            Err(Error::CommitmentMismatch)
+        }
+    }
+
+    /// Checks that a file on disk is the one that was timestamped
+    pub fn check_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.check_reader(fs::File::open(path)?)
+    }
+
+    /// Reads a timestamp file from disk
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<DetachedTimestampFile, Error> {
+        let fh = fs::File::open(path)?;
+        DetachedTimestampFile::from_reader(io::BufReader::new(fh))
+    }
+
+    /// Writes the timestamp to disk. The file is written under a temporary
+    /// name alongside its final location and then renamed over it, so a
+    /// crash never leaves a truncated proof behind, even when replacing an
+    /// older one.
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        write_atomic(path, |writer| self.to_writer(writer))
+    }
+
+    /// The conventional path of the timestamp of a file, which is its
+    /// own path with `.ots` appended
+    pub fn proof_path<P: AsRef<Path>>(target: P) -> PathBuf {
+        let mut path = target.as_ref().as_os_str().to_owned();
+        path.push(".");
+        path.push(EXTENSION);
+        PathBuf::from(path)
+    }
+
+    /// The path of the file timestamped by a `.ots` file, if the proof
+    /// has that extension
+    pub fn target_path<P: AsRef<Path>>(proof: P) -> Option<PathBuf> {
+        let proof = proof.as_ref();
+        if proof.extension() == Some(EXTENSION.as_ref()) {
+            Some(proof.with_extension(""))
+        } else {
+            None
+        }
+    }
+}
+
+/// Distinguishes temporary files written by this process
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a file by writing a temporary file alongside it and renaming it
+/// into place. The temporary name is unique to this write, so concurrent
+/// writers to the same path do not clobber each other's partial output,
+/// and it is removed if writing fails.
+pub(crate) fn write_atomic<P, F>(path: P, write: F) -> Result<(), Error>
+    where P: AsRef<Path>,
+          F: FnOnce(&mut io::BufWriter<fs::File>) -> Result<(), Error>
+{
+    let path = path.as_ref();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.{}.tmp", process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+    let result = (|| {
+        let mut writer = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        write(&mut writer)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
 }
 
 impl fmt::Display for DetachedTimestampFile {
@@ -90,7 +260,8 @@ impl fmt::Display for DetachedTimestampFile {
 pub enum DigestType {
     Sha1,
     Sha256,
-    Ripemd160
+    Ripemd160,
+    Keccak256
 }
 
 impl DigestType {
@@ -99,6 +270,7 @@ impl DigestType {
         match tag {
             0x02 => Ok(DigestType::Sha1),
             0x03 => Ok(DigestType::Ripemd160),
+            0x67 => Ok(DigestType::Keccak256),
             0x08 => Ok(DigestType::Sha256),
             x => Err(Error::BadDigestTag(x))
         }
@@ -109,7 +281,8 @@ impl DigestType {
         match self {
             DigestType::Sha1 => 0x02,
             DigestType::Sha256 => 0x08,
-            DigestType::Ripemd160 => 0x03
+            DigestType::Ripemd160 => 0x03,
+            DigestType::Keccak256 => 0x67
         }
     }
 
@@ -118,8 +291,38 @@ impl DigestType {
         match self {
             DigestType::Sha1 => 20,
             DigestType::Sha256 => 32,
-            DigestType::Ripemd160 => 20
+            DigestType::Ripemd160 => 20,
+            DigestType::Keccak256 => 32
+        }
+    }
+
+    /// Computes the digest of everything read from a reader, using a
+    /// fixed-size buffer so that input of any size can be hashed
+    pub fn digest_reader<R: Read>(self, mut reader: R) -> Result<Vec<u8>, Error> {
+        let mut hasher: Box<dyn Digest> = match self {
+            DigestType::Sha1 => Box::new(Sha1::new()),
+            DigestType::Sha256 => Box::new(Sha256::new()),
+            DigestType::Ripemd160 => Box::new(Ripemd160::new()),
+            DigestType::Keccak256 => Box::new(Sha3::keccak256())
+        };
+        let mut buf = vec![0; 65536];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => hasher.input(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::Io(e))
+            }
         }
+        let mut ret = vec![0; self.digest_len()];
+        hasher.result(&mut ret);
+        Ok(ret)
+    }
+
+    /// Computes the digest of a file, reading it in chunks rather than
+    /// loading it into memory
+    pub fn digest_file<P: AsRef<Path>>(self, path: P) -> Result<Vec<u8>, Error> {
+        self.digest_reader(fs::File::open(path)?)
     }
 }
 
@@ -129,6 +332,7 @@ impl fmt::Display for DigestType {
             DigestType::Sha1 => f.write_str("SHA1"),
             DigestType::Sha256 => f.write_str("SHA256"),
             DigestType::Ripemd160 => f.write_str("RIPEMD160"),
+            DigestType::Keccak256 => f.write_str("KECCAK256"),
         }
     }
 }
@@ -136,17 +340,100 @@ impl fmt::Display for DigestType {
 
 // ** I/O stuff **
 
+/// Limits on what a `Deserializer` will accept, bounding the memory and
+/// time a hostile proof can make us spend. The defaults are generous
+/// enough for any proof produced by the public calendars, and parse as
+/// strictly as python-opentimestamps.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Limits {
+    /// Maximum number of steps (ops, forks and attestations) in total
+    pub max_steps: usize,
+    /// Maximum number of branches of a single fork
+    pub max_fork_branches: usize,
+    /// Maximum size of an attestation's payload
+    pub max_attestation_payload: usize,
+    /// Maximum length of the result of any op
+    pub max_result_length: usize,
+    /// Whether to reject attestations whose payloads have bytes left
+    /// over after parsing, as python-opentimestamps does, and integers
+    /// encoded with more bytes than necessary. Either would let the same
+    /// proof be serialized in more than one way.
+    pub strict: bool
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_steps: 65536,
+            max_fork_branches: 4096,
+            // as in python-opentimestamps
+            max_attestation_payload: 8192,
+            max_result_length: MAX_RESULT_LENGTH,
+            strict: true
+        }
+    }
+}
+
 /// Standard deserializer for OTS info files
 pub struct Deserializer<R: Read> {
-    reader: R
+    reader: R,
+    limits: Limits,
+    steps: usize,
+    position: usize,
+    item_start: usize
 }
 
 impl<R: Read> Deserializer<R> {
     /// Constructs a new deserializer from a reader
     pub fn new(reader: R) -> Deserializer<R> {
+        Deserializer::with_limits(reader, Limits::default())
+    }
+
+    /// Constructs a new deserializer with non-default limits
+    pub fn with_limits(reader: R, limits: Limits) -> Deserializer<R> {
         Deserializer {
-            reader: reader
+            reader,
+            limits,
+            steps: 0,
+            position: 0,
+            item_start: 0
+        }
+    }
+
+    /// Number of bytes read so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => {
+                self.position += buf.len();
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::Truncated),
+            Err(e) => Err(Error::Io(e))
+        }
+    }
+
+    /// The limits this deserializer enforces
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Records that a timestamp step has been read, failing if this
+    /// deserializer has now read more than `max_steps` of them
+    pub fn count_step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps > self.limits.max_steps {
+            return Err(Error::LimitExceeded { limit: "steps", max: self.limits.max_steps });
         }
+        Ok(())
+    }
+
+    /// Number of timestamp steps read so far
+    pub fn step_count(&self) -> usize {
+        self.steps
     }
 
     /// Extracts the underlying reader from the deserializer
@@ -177,8 +464,9 @@ impl<R: Read> Deserializer<R> {
 
     /// Reads a single byte from the reader
     pub fn read_byte(&mut self) -> Result<u8, Error> {
+        self.item_start = self.position;
         let mut byte = [0];
-        self.reader.read_exact(&mut byte)?;
+        self.read_exact(&mut byte)?;
         Ok(byte[0])
     }
 
@@ -187,42 +475,60 @@ impl<R: Read> Deserializer<R> {
 
     /// Deserializes an unsigned integer
     pub fn read_uint(&mut self) -> Result<usize, Error> {
+        let start = self.position;
         let mut ret = 0;
         let mut shift = 0;
 
         loop {
             // Bottom 7 bits are value bits
             let byte = self.read_byte()?;
-            ret |= ((byte & 0x7f) as usize) << shift;
+            let bits = (byte & 0x7f) as usize;
+            if shift >= usize::BITS as usize || (shift > 0 && bits >> (usize::BITS as usize - shift) != 0) {
+                self.item_start = start;
+                return Err(Error::VarintOverflow);
+            }
+            ret |= bits << shift;
             // Top bit is a continue bit
             if byte & 0x80 == 0 {
+                // A final zero byte adds nothing, so could have been left off
+                if self.limits.strict && shift > 0 && byte == 0 {
+                    self.item_start = start;
+                    return Err(Error::NonMinimalVarint);
+                }
                 break;
             }
             shift += 7;
         }
 
+        self.item_start = start;
         Ok(ret)
     }
 
     /// Deserializes a fixed number of bytes
     pub fn read_fixed_bytes(&mut self, n: usize) -> Result<Vec<u8>, Error> {
+        self.item_start = self.position;
         let mut ret = vec![0; n];
-        self.reader.read_exact(&mut ret)?;
+        self.read_exact(&mut ret)?;
         Ok(ret)
     }
 
     /// Deserializes a variable number of bytes
     pub fn read_bytes(&mut self, min: usize, max: usize) -> Result<Vec<u8>, Error> {
+        let start = self.position;
         let n = self.read_uint()?;
         if n < min || n > max {
-            return Err(Error::BadLength { min: min, max: max, val: n });
+            return Err(Error::BadLength { min, max, val: n });
         }
-        self.read_fixed_bytes(n)
+        let ret = self.read_fixed_bytes(n)?;
+        self.item_start = start;
+        Ok(ret)
     }
 
     /// Check that there is no trailing data
     pub fn check_eof(&mut self) -> Result<(), Error> {
-        if self.reader.by_ref().bytes().next().is_none() {
+        self.item_start = self.position;
+        let mut byte = [0];
+        if self.reader.read(&mut byte)? == 0 {
             Ok(())
         } else {
             Err(Error::TrailingBytes)
@@ -240,7 +546,7 @@ impl<W: Write> Serializer<W> {
     /// Constructs a new deserializer from a reader
     pub fn new(writer: W) -> Serializer<W> {
         Serializer {
-            writer: writer
+            writer
         }
     }
 
@@ -295,9 +601,11 @@ impl<W: Write> Serializer<W> {
 
 #[cfg(test)]
 mod tests {
+    use tests::{LARGE_TEST, SMALL_TEST};
     use super::*;
 
     #[test]
+    #[allow(unused_macros, redundant_semicolons)]
     fn digest_type_rt() {
         macro_rules! check_digest_type {
             ($($tag: ident),*) => {
@@ -316,11 +624,266 @@ mod tests {
         };
     }
 
+    #[test]
+    fn digest_type_tags() {
+        for &digest_type in &[DigestType::Sha1, DigestType::Sha256, DigestType::Ripemd160, DigestType::Keccak256] {
+            // Exhaustiveness check, so new digest types are added above
+            match digest_type {
+                DigestType::Sha1 | DigestType::Sha256 | DigestType::Ripemd160 | DigestType::Keccak256 => {}
+            }
+            assert_eq!(DigestType::from_tag(digest_type.to_tag()).unwrap(), digest_type);
+        }
+    }
+
     #[test]
     fn digest_len() {
         assert_eq!(DigestType::Sha1.digest_len(), 20);
         assert_eq!(DigestType::Sha256.digest_len(), 32);
         assert_eq!(DigestType::Ripemd160.digest_len(), 20);
+        assert_eq!(DigestType::Keccak256.digest_len(), 32);
+    }
+
+    #[test]
+    fn digest_file() {
+        use op::Op;
+        use std::{env, process};
+
+        // Larger than one read buffer, so it is hashed in several chunks
+        let data: Vec<u8> = (0..200000u32).map(|i| i as u8).collect();
+        let path = env::temp_dir().join(format!("ots-digest-file-{}", process::id()));
+        fs::write(&path, &data).unwrap();
+        for &(digest_type, ref op) in &[(DigestType::Sha1, Op::Sha1),
+                                        (DigestType::Sha256, Op::Sha256),
+                                        (DigestType::Ripemd160, Op::Ripemd160),
+                                        (DigestType::Keccak256, Op::Keccak256)] {
+            assert_eq!(digest_type.digest_file(&path).unwrap(), op.execute(&data));
+            assert_eq!(digest_type.digest_reader(&data[..]).unwrap(), op.execute(&data));
+            assert_eq!(digest_type.digest_reader(&b""[..]).unwrap(), op.execute(b""));
+        }
+        fs::remove_file(&path).unwrap();
+        assert!(DigestType::Sha256.digest_file(&path).is_err());
     }
-}
 
+    #[test]
+    fn prefix() {
+        let mut data = LARGE_TEST.to_vec();
+        data.extend_from_slice(b"trailing data");
+        let (ots, len) = DetachedTimestampFile::from_reader_prefix(&data[..]).unwrap();
+        assert_eq!(len, LARGE_TEST.len());
+        assert_eq!(ots, DetachedTimestampFile::from_reader(LARGE_TEST).unwrap());
+
+        let limits = Limits { max_steps: 10, ..Default::default() };
+        match DetachedTimestampFile::from_reader_prefix_with_limits(&data[..], limits) {
+            Err(ref e) if matches!(e.root(), Error::LimitExceeded { limit: "steps", max: 10 }) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        match DetachedTimestampFile::from_reader(&data[..]) {
+            Err(Error::AtOffset { offset, error }) => {
+                assert_eq!(offset, LARGE_TEST.len());
+                assert!(matches!(*error, Error::TrailingBytes));
+            }
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn concatenated() {
+        let small = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let large = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let files = vec![small, large.clone(), large];
+
+        let mut data = vec![];
+        DetachedTimestampFile::to_writer_all(&files, &mut data).unwrap();
+        assert_eq!(data.len(), SMALL_TEST.len() + 2 * LARGE_TEST.len());
+        assert_eq!(DetachedTimestampFile::from_reader_all(&data[..]).unwrap(), files);
+        assert_eq!(DetachedTimestampFile::from_reader_all(&b""[..]).unwrap(), vec![]);
+
+        // Errors are reported relative to the whole stream
+        data.truncate(data.len() - 1);
+        match DetachedTimestampFile::from_reader_all(&data[..]) {
+            Err(Error::AtOffset { offset, error }) => {
+                assert!(offset > SMALL_TEST.len() + LARGE_TEST.len());
+                assert!(matches!(error.root(), Error::Truncated));
+            }
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // So are read failures between proofs
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::Other, "disk on fire"))
+            }
+        }
+        match DetachedTimestampFile::from_reader_all(SMALL_TEST.chain(Failing)) {
+            Err(Error::AtOffset { offset, error }) => {
+                assert_eq!(offset, SMALL_TEST.len());
+                assert!(matches!(*error, Error::Io(_)));
+            }
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn check_reader() {
+        use attestation::Attestation;
+        use op::Op;
+        use timestamp::{Step, StepData};
+
+        let digest = Op::Sha256.execute(b"Hello World!\n");
+        let ots = DetachedTimestampFile {
+            digest_type: DigestType::Sha256,
+            timestamp: Timestamp {
+                start_digest: digest.clone(),
+                first_step: Step {
+                    data: StepData::Attestation(Attestation::Bitcoin { height: 1 }),
+                    output: digest,
+                    next: vec![]
+                }
+            }
+        };
+        assert!(ots.check_reader(&b"Hello World!\n"[..]).is_ok());
+        match ots.check_reader(&b"Hello World!"[..]) {
+            Err(Error::CommitmentMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn read_write_path() {
+        use std::{env, process};
+
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let target = env::temp_dir().join(format!("ots-read-write-{}.txt", process::id()));
+        let path = DetachedTimestampFile::proof_path(&target);
+        assert_eq!(path.file_name().unwrap(), &*format!("ots-read-write-{}.txt.ots", process::id()));
+        assert_eq!(DetachedTimestampFile::target_path(&path), Some(target.clone()));
+        assert_eq!(DetachedTimestampFile::target_path(&target), None);
+
+        // Writing twice replaces the first copy
+        ots.write_to_path(&path).unwrap();
+        ots.write_to_path(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), LARGE_TEST);
+        assert_eq!(DetachedTimestampFile::read_from_path(&path).unwrap(), ots);
+        fs::remove_file(&path).unwrap();
+
+        // A failed write leaves nothing behind
+        let dir = env::temp_dir().join(format!("ots-read-write-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("proof.ots");
+        assert!(write_atomic(&path, |_| Err(Error::TrailingBytes)).is_err());
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn offsets() {
+        let at = |data: &[u8]| match DetachedTimestampFile::from_reader(data) {
+            Err(Error::AtOffset { offset, error }) => (offset, error),
+            x => panic!("unexpected result {:?}", x)
+        };
+
+        // Header is 31 magic bytes, version, digest type and 32-byte digest
+        let (offset, error) = at(&LARGE_TEST[..20]);
+        assert_eq!(offset, 0);
+        assert!(matches!(*error, Error::InHeader(_)));
+        assert!(matches!(error.root(), Error::Truncated));
+        let (offset, error) = at(&LARGE_TEST[..60]);
+        assert_eq!(offset, 33);
+        assert!(matches!(error.root(), Error::Truncated));
+        let mut data = LARGE_TEST.to_vec();
+        data[65] = 0x55;
+        let (offset, error) = at(&data);
+        assert_eq!(offset, 65);
+        assert!(matches!(error.root(), Error::BadOpTag(0x55)));
+        let mut data = LARGE_TEST.to_vec();
+        data.push(0);
+        let (offset, error) = at(&data);
+        assert_eq!(offset, LARGE_TEST.len());
+        assert!(matches!(*error, Error::TrailingBytes));
+    }
+
+    #[test]
+    fn error_context() {
+        let mut data = LARGE_TEST.to_vec();
+        data[65] = 0x55;
+        let error = DetachedTimestampFile::from_reader(&data[..]).unwrap_err();
+        assert_eq!(format!("{}", error), "at byte 65: in op at step 1: invalid op tag 0x55");
+
+        // Corrupt the URI of the first pending attestation
+        let pos = LARGE_TEST.windows(5).position(|w| w == b"https").unwrap();
+        let mut data = LARGE_TEST.to_vec();
+        data[pos] = b'?';
+        match DetachedTimestampFile::from_reader(&data[..]).unwrap_err() {
+            Error::AtOffset { error, .. } => match *error {
+                Error::InStep { kind: "attestation", error, .. } => {
+                    assert!(matches!(*error, Error::InvalidUriChar('?')));
+                }
+                x => panic!("unexpected result {:?}", x)
+            },
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        let error = DetachedTimestampFile::from_reader(&LARGE_TEST[..20]).unwrap_err();
+        assert_eq!(format!("{}", error), "at byte 0: in file header: unexpected end of data");
+
+        // Context-free errors are reachable through `root`
+        let error = DetachedTimestampFile::from_reader(&[0; 64][..]).unwrap_err();
+        assert!(!matches!(error, Error::BadMagic(_)));
+        assert!(matches!(error.root(), Error::BadMagic(_)));
+    }
+
+    #[test]
+    fn varint_overflow() {
+        let mut deser = Deserializer::new(&b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\x01"[..]);
+        assert_eq!(deser.read_uint().unwrap(), usize::MAX);
+        let mut deser = Deserializer::new(&b"\xff\xff\xff\xff\xff\xff\xff\xff\xff\x02"[..]);
+        match deser.read_uint() {
+            Err(Error::VarintOverflow) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        let mut deser = Deserializer::new(&b"\x80\x80\x80\x80\x80\x80\x80\x80\x80\x80\x00"[..]);
+        assert!(deser.read_uint().is_err());
+    }
+
+    #[test]
+    fn minimal_varint() {
+        for &(data, val) in &[(&b"\x00"[..], 0), (b"\x7f", 127), (b"\x80\x01", 128), (b"\xff\x7f", 16383)] {
+            assert_eq!(Deserializer::new(data).read_uint().unwrap(), val);
+        }
+        let lenient = Limits { strict: false, ..Default::default() };
+        for &(data, val) in &[(&b"\x80\x00"[..], 0), (b"\x81\x00", 1), (b"\x80\x81\x80\x00", 128)] {
+            match Deserializer::new(data).read_uint() {
+                Err(Error::NonMinimalVarint) => {}
+                x => panic!("unexpected result {:?}", x)
+            }
+            assert_eq!(Deserializer::with_limits(data, lenient).read_uint().unwrap(), val);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let parse = |limits| match DetachedTimestampFile::from_reader_with_limits(LARGE_TEST, limits) {
+            Ok(ots) => Ok(ots),
+            Err(Error::AtOffset { error, .. }) => Err(error),
+            Err(e) => panic!("error without offset {:?}", e)
+        };
+        let ots = parse(Limits::default()).unwrap();
+        assert_eq!(ots, DetachedTimestampFile::from_reader(LARGE_TEST).unwrap());
+
+        match parse(Limits { max_steps: 10, ..Limits::default() }) {
+            Err(ref e) if matches!(e.root(), Error::LimitExceeded { limit: "steps", max: 10 }) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        match parse(Limits { max_fork_branches: 1, ..Limits::default() }) {
+            Err(ref e) if matches!(e.root(), Error::LimitExceeded { limit: "fork branches", max: 1 }) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        assert!(parse(Limits { max_fork_branches: 2, ..Limits::default() }).is_ok());
+        match parse(Limits { max_attestation_payload: 10, ..Limits::default() }) {
+            Err(ref e) if matches!(e.root(), Error::BadLength { max: 10, .. }) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+}
@@ -0,0 +1,391 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # SPV Validation
+//!
+//! Wrapper around an untrusted `BlockHeaderSource` (a block explorer, an
+//! Electrum server) which validates the headers it returns. Starting from
+//! a trusted checkpoint, every header up to the requested one is fetched
+//! and checked for continuity, sufficient proof-of-work, and correct
+//! difficulty adjustments. Only mainnet-style retargeting is implemented;
+//! testnet's minimum-difficulty exception is not.
+//!
+
+use std::cell::RefCell;
+
+use error::Error;
+use headers::HeaderChain;
+use verify::{BlockHeader, BlockHeaderSource};
+
+/// Consensus parameters needed to validate a header chain
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Params {
+    /// Easiest allowed target, in compact form
+    pub pow_limit: u32,
+    /// Number of blocks between difficulty adjustments
+    pub retarget_interval: usize,
+    /// Intended duration of a retarget interval, in seconds
+    pub target_timespan: u32
+}
+
+impl Params {
+    /// Parameters of the Bitcoin main network
+    pub fn bitcoin() -> Params {
+        Params {
+            pow_limit: 0x1d00ffff,
+            retarget_interval: 2016,
+            target_timespan: 14 * 24 * 60 * 60
+        }
+    }
+
+    /// Checks that the parameters can be used to validate a chain
+    pub fn check(&self) -> Result<(), Error> {
+        if self.retarget_interval == 0 {
+            return Err(Error::BadParams("retarget interval is zero"));
+        }
+        // Timespans are clamped to four times the target, which must fit a u32
+        if self.target_timespan == 0 || self.target_timespan > u32::MAX / 4 {
+            return Err(Error::BadParams("target timespan out of range"));
+        }
+        if Uint256::from_compact(self.pow_limit).is_none() {
+            return Err(Error::BadParams("pow limit is not a valid target"));
+        }
+        Ok(())
+    }
+}
+
+/// A header source which validates the headers of another source
+pub struct SpvSource<S: BlockHeaderSource> {
+    inner: S,
+    params: Params,
+    chain: RefCell<HeaderChain>
+}
+
+impl<S: BlockHeaderSource> SpvSource<S> {
+    /// Wraps a header source, trusting the given checkpoint. The
+    /// checkpoint must be the first block of a difficulty period, so
+    /// that every later adjustment can be checked.
+    pub fn new(inner: S, params: Params, checkpoint_height: usize, checkpoint: BlockHeader) -> Result<SpvSource<S>, Error> {
+        SpvSource::from_chain(inner, params, HeaderChain::new(checkpoint_height, checkpoint))
+    }
+
+    /// Wraps a header source, trusting a previously validated chain
+    /// such as one saved by a past `SpvSource`. Fails if `params` does
+    /// not pass `Params::check`.
+    pub fn from_chain(inner: S, params: Params, chain: HeaderChain) -> Result<SpvSource<S>, Error> {
+        params.check()?;
+        if chain.start_height() % params.retarget_interval != 0 {
+            return Err(Error::BadCheckpoint(chain.start_height()));
+        }
+        Ok(SpvSource {
+            inner,
+            params,
+            chain: RefCell::new(chain)
+        })
+    }
+
+    /// Extracts the chain of headers validated so far, e.g. to save it
+    pub fn into_chain(self) -> HeaderChain {
+        self.chain.into_inner()
+    }
+
+    /// Checks a header which is to extend `chain`
+    fn check_header(&self, chain: &HeaderChain, header: &BlockHeader) -> Result<(), Error> {
+        let height = chain.tip_height() + 1;
+        let tip = chain.tip();
+
+        let expected_bits = if height % self.params.retarget_interval == 0 {
+            let first = chain.header(height - self.params.retarget_interval).expect("checkpoint at period start");
+            next_bits(&self.params, tip.bits, tip.time as i64 - first.time as i64)
+        } else {
+            tip.bits
+        };
+        if header.bits != expected_bits {
+            return Err(Error::BadDifficulty(height));
+        }
+
+        let mut hash = header.block_hash();
+        hash.reverse();
+        match Uint256::from_compact(header.bits) {
+            Some(target) if Uint256(hash) <= target => Ok(()),
+            _ => Err(Error::BadProofOfWork(height))
+        }
+    }
+}
+
+impl<S: BlockHeaderSource> BlockHeaderSource for SpvSource<S> {
+    fn block_header(&self, height: usize) -> Result<BlockHeader, Error> {
+        let mut chain = self.chain.borrow_mut();
+        if height < chain.start_height() {
+            return Err(Error::NoHeader(height));
+        }
+        while chain.tip_height() < height {
+            let header = self.inner.block_header(chain.tip_height() + 1)?;
+            self.check_header(&chain, &header)?;
+            chain.push(header)?;
+        }
+        Ok(*chain.header(height).expect("just extended chain"))
+    }
+}
+
+/// Computes the compact target of the first block of a difficulty period,
+/// given the target of the previous period and the time it took.
+/// Panics if `params` does not pass `Params::check`.
+pub fn next_bits(params: &Params, last_bits: u32, timespan: i64) -> u32 {
+    let target_timespan = params.target_timespan as i64;
+    let timespan = if timespan < target_timespan / 4 {
+        target_timespan / 4
+    } else if timespan > target_timespan * 4 {
+        target_timespan * 4
+    } else {
+        timespan
+    };
+
+    let limit = Uint256::from_compact(params.pow_limit).expect("valid pow limit");
+    let old = Uint256::from_compact(last_bits).unwrap_or(limit);
+    let new = old.mul_div_u32(timespan as u32, params.target_timespan);
+    if new > limit {
+        limit.to_compact()
+    } else {
+        new.to_compact()
+    }
+}
+
+/// Just enough of a 256-bit unsigned integer for target computations,
+/// stored big-endian so that the derived ordering is numeric
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Uint256([u8; 32]);
+
+impl Uint256 {
+    /// Decodes a compact target, returning `None` for zero, negative or
+    /// overflowing encodings, none of which are valid targets
+    fn from_compact(bits: u32) -> Option<Uint256> {
+        let size = (bits >> 24) as usize;
+        let word = bits & 0x007fffff;
+        if word == 0 || bits & 0x00800000 != 0 {
+            return None;
+        }
+
+        let mut ret = [0; 32];
+        for k in 0..3 {
+            let byte = (word >> (16 - 8 * k)) as u8;
+            if size < k + 1 {
+                continue;
+            }
+            // position of this byte counting from the least significant
+            let pos = size - 1 - k;
+            if pos >= 32 {
+                if byte != 0 {
+                    return None;
+                }
+            } else {
+                ret[31 - pos] = byte;
+            }
+        }
+        if ret == [0; 32] {
+            None
+        } else {
+            Some(Uint256(ret))
+        }
+    }
+
+    /// Encodes as a compact target
+    fn to_compact(self) -> u32 {
+        let first = match self.0.iter().position(|b| *b != 0) {
+            Some(idx) => idx,
+            None => return 0
+        };
+        let mut size = 32 - first;
+        let mut word = 0u32;
+        for k in 0..3 {
+            word <<= 8;
+            if first + k < 32 {
+                word |= self.0[first + k] as u32;
+            }
+        }
+        if word & 0x00800000 != 0 {
+            word >>= 8;
+            size += 1;
+        }
+        ((size as u32) << 24) | word
+    }
+
+    /// Computes `self * m / d`, using a wider intermediate so that the
+    /// product cannot overflow, and saturating if the quotient does
+    fn mul_div_u32(self, m: u32, d: u32) -> Uint256 {
+        let mut wide = [0u8; 36];
+        let mut carry = 0u64;
+        for (w, b) in wide[4..].iter_mut().zip(self.0.iter()).rev() {
+            let acc = *b as u64 * m as u64 + carry;
+            *w = acc as u8;
+            carry = acc >> 8;
+        }
+        for w in wide[..4].iter_mut().rev() {
+            *w = carry as u8;
+            carry >>= 8;
+        }
+
+        let mut rem = 0u64;
+        for w in wide.iter_mut() {
+            rem = (rem << 8) | *w as u64;
+            *w = (rem / d as u64) as u8;
+            rem %= d as u64;
+        }
+
+        if wide[..4] != [0; 4] {
+            return Uint256([0xff; 32]);
+        }
+        let mut ret = [0; 32];
+        ret.copy_from_slice(&wide[4..]);
+        Uint256(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct VecSource(Vec<BlockHeader>);
+
+    impl BlockHeaderSource for VecSource {
+        fn block_header(&self, height: usize) -> Result<BlockHeader, Error> {
+            self.0.get(height).cloned().ok_or(Error::NoHeader(height))
+        }
+    }
+
+    fn test_params() -> Params {
+        Params {
+            pow_limit: 0x207fffff,
+            retarget_interval: 8,
+            target_timespan: 8 * 600
+        }
+    }
+
+    fn meets_target(header: &BlockHeader) -> bool {
+        let mut hash = header.block_hash();
+        hash.reverse();
+        Uint256(hash) <= Uint256::from_compact(header.bits).unwrap()
+    }
+
+    /// Mines a chain with blocks every 60 seconds, so that the
+    /// difficulty goes up at every adjustment
+    fn mine(params: &Params, n: usize) -> Vec<BlockHeader> {
+        let mut ret: Vec<BlockHeader> = vec![];
+        for height in 0..n {
+            let bits = if height == 0 {
+                params.pow_limit
+            } else if height % params.retarget_interval == 0 {
+                let first = &ret[height - params.retarget_interval];
+                next_bits(params, ret[height - 1].bits, ret[height - 1].time as i64 - first.time as i64)
+            } else {
+                ret[height - 1].bits
+            };
+            let mut header = BlockHeader {
+                version: 1,
+                prev_blockhash: if height == 0 { [0; 32] } else { ret[height - 1].block_hash() },
+                merkle_root: [height as u8; 32],
+                time: 1500000000 + 60 * height as u32,
+                bits,
+                nonce: 0
+            };
+            while !meets_target(&header) {
+                header.nonce += 1;
+            }
+            ret.push(header);
+        }
+        ret
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        for bits in &[0x1d00ffff, 0x207fffff, 0x1b0404cb, 0x1e0377ae, 0x03123456, 0x02008000] {
+            assert_eq!(Uint256::from_compact(*bits).unwrap().to_compact(), *bits);
+        }
+        assert!(Uint256::from_compact(0).is_none());
+        assert!(Uint256::from_compact(0x04923456).is_none());
+        assert!(Uint256::from_compact(0xff123456).is_none());
+    }
+
+    #[test]
+    fn retarget() {
+        // Signet's first adjustment, at block 2016
+        let params = Params { pow_limit: 0x1e0377ae, retarget_interval: 2016, target_timespan: 1209600 };
+        assert_eq!(next_bits(&params, 0x1e0377ae, 1599332177 - 1598918400), 503394215);
+        // Clamped to the pow limit
+        assert_eq!(next_bits(&Params::bitcoin(), 0x1d00ffff, 10 * 1209600), 0x1d00ffff);
+        // Clamped to a factor of four
+        assert_eq!(next_bits(&Params::bitcoin(), 0x1c00ffff, 1), next_bits(&Params::bitcoin(), 0x1c00ffff, 1209600 / 4));
+    }
+
+    #[test]
+    fn validate_chain() {
+        let params = test_params();
+        let headers = mine(&params, 20);
+        assert!(headers[8].bits != headers[7].bits);
+        assert!(headers[16].bits != headers[15].bits);
+
+        let spv = SpvSource::new(VecSource(headers.clone()), params, 0, headers[0]).unwrap();
+        assert_eq!(spv.block_header(19).unwrap(), headers[19]);
+        assert_eq!(spv.block_header(5).unwrap(), headers[5]);
+        assert_eq!(spv.into_chain().tip_height(), 19);
+
+        // Checkpoint mid-period
+        assert!(SpvSource::new(VecSource(headers.clone()), params, 3, headers[3]).is_err());
+
+        // Unusable parameters are rejected up front
+        for bad in &[
+            Params { retarget_interval: 0, ..params },
+            Params { target_timespan: 0, ..params },
+            Params { target_timespan: u32::MAX, ..params },
+            Params { pow_limit: 0, ..params },
+        ] {
+            match SpvSource::new(VecSource(headers.clone()), *bad, 0, headers[0]) {
+                Err(Error::BadParams(_)) => {}
+                Err(e) => panic!("unexpected error {:?}", e),
+                Ok(_) => panic!("accepted {:?}", bad)
+            }
+        }
+        Params::bitcoin().check().unwrap();
+
+        // Unchanged difficulty at an adjustment
+        let mut bad = headers.clone();
+        bad[8].bits = bad[7].bits;
+        let spv = SpvSource::new(VecSource(bad), params, 0, headers[0]).unwrap();
+        match spv.block_header(10) {
+            Err(Error::BadDifficulty(8)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Insufficient work
+        let mut bad = headers.clone();
+        while meets_target(&bad[12]) {
+            bad[12].nonce += 1;
+        }
+        let spv = SpvSource::new(VecSource(bad), params, 0, headers[0]).unwrap();
+        assert_eq!(spv.block_header(11).unwrap(), headers[11]);
+        match spv.block_header(12) {
+            Err(Error::BadProofOfWork(12)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Not connected
+        let mut bad = headers.clone();
+        bad[3] = bad[4];
+        let spv = SpvSource::new(VecSource(bad), params, 0, headers[0]).unwrap();
+        match spv.block_header(3) {
+            Err(Error::BrokenChain(3)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+}
@@ -0,0 +1,192 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Store
+//!
+//! Persistent store of timestamps keyed by their start digest, e.g. for
+//! tracking pending proofs across restarts while they are upgraded. Each
+//! proof is kept as an ordinary `.ots` file, named by the hex digest, in
+//! a single directory, and is replaced atomically when written back.
+//!
+//! The store takes no locks, so it assumes a single writer: readers always
+//! see a complete proof, but if two processes, or two `TimestampStore`s in
+//! one process, `merge` into the same digest at once, one of the merges
+//! may be lost. Callers with several writers must serialize them.
+//!
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use error::Error;
+use hex::{self, Hexed};
+use ser::{self, DetachedTimestampFile};
+
+/// A directory of timestamps keyed by digest
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TimestampStore {
+    dir: PathBuf
+}
+
+impl TimestampStore {
+    /// Opens the store in a directory, creating it if necessary
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<TimestampStore, Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(TimestampStore { dir: dir.as_ref().to_owned() })
+    }
+
+    /// The path of the file holding the timestamp for `digest`
+    pub fn path(&self, digest: &[u8]) -> PathBuf {
+        self.dir.join(format!("{}.{}", Hexed(digest), ser::EXTENSION))
+    }
+
+    /// Stores a timestamp, replacing any already stored for its digest.
+    /// The stored proof is overwritten even if it has attestations which
+    /// `ots` lacks; use `merge` to keep them.
+    pub fn put(&self, ots: &DetachedTimestampFile) -> Result<(), Error> {
+        ots.write_to_path(self.path(&ots.timestamp.start_digest))
+    }
+
+    /// Stores a timestamp, merging it into any already stored for its
+    /// digest, and returns the result. This suits writing back upgraded
+    /// proofs, since it never loses attestations, provided there is no
+    /// other writer; the read and the write back are not one atomic step.
+    pub fn merge(&self, ots: DetachedTimestampFile) -> Result<DetachedTimestampFile, Error> {
+        let merged = match self.get(&ots.timestamp.start_digest)? {
+            Some(mut stored) => {
+                if stored.digest_type != ots.digest_type {
+                    return Err(Error::CommitmentMismatch);
+                }
+                stored.timestamp.merge(ots.timestamp)?;
+                stored
+            }
+            None => ots
+        };
+        self.put(&merged)?;
+        Ok(merged)
+    }
+
+    /// Looks up the timestamp for a digest
+    pub fn get(&self, digest: &[u8]) -> Result<Option<DetachedTimestampFile>, Error> {
+        match DetachedTimestampFile::read_from_path(self.path(digest)) {
+            Ok(ots) => Ok(Some(ots)),
+            Err(ref e) if matches!(e.root(), Error::Io(ref e) if e.kind() == io::ErrorKind::NotFound) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Removes the timestamp for a digest, returning whether there was one
+    pub fn remove(&self, digest: &[u8]) -> Result<bool, Error> {
+        match fs::remove_file(self.path(digest)) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(Error::Io(e))
+        }
+    }
+
+    /// Lists the digests with stored timestamps, in sorted order. Files
+    /// not named exactly as `path` names them, e.g. with uppercase hex,
+    /// are ignored.
+    pub fn digests(&self) -> Result<Vec<Vec<u8>>, Error> {
+        let mut ret = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension() != Some(ser::EXTENSION.as_ref()) {
+                continue;
+            }
+            let digest = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| hex::decode(stem).ok());
+            match digest {
+                Some(digest) if path == self.path(&digest) => ret.push(digest),
+                _ => {}
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Loads every stored timestamp, in the order of `digests`
+    pub fn load_all(&self) -> Result<Vec<DetachedTimestampFile>, Error> {
+        let mut ret = vec![];
+        for digest in self.digests()? {
+            if let Some(ots) = self.get(&digest)? {
+                ret.push(ots);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use attestation::Attestation;
+    use tests::{LARGE_TEST, SMALL_TEST};
+    use super::*;
+
+    #[test]
+    fn put_get() {
+        let dir = env::temp_dir().join(format!("ots-store-{}", process::id()));
+        let store = TimestampStore::open(&dir).unwrap();
+        let small = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let large = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        assert_eq!(store.get(&small.timestamp.start_digest).unwrap(), None);
+
+        store.put(&small).unwrap();
+        store.put(&large).unwrap();
+        store.put(&large).unwrap();
+        fs::write(dir.join("README"), b"not a timestamp").unwrap();
+        // Uppercase names are not what `put` writes, so `get` would miss them
+        let upper = format!("{}", Hexed(&[0xab; 32])).to_uppercase();
+        fs::write(dir.join(format!("{}.ots", upper)), LARGE_TEST).unwrap();
+        assert_eq!(store.get(&small.timestamp.start_digest).unwrap(), Some(small.clone()));
+
+        let mut digests = vec![small.timestamp.start_digest.clone(), large.timestamp.start_digest.clone()];
+        digests.sort();
+        assert_eq!(store.digests().unwrap(), digests);
+        assert_eq!(store.load_all().unwrap().len(), 2);
+
+        // A reopened store sees the same contents
+        let store = TimestampStore::open(&dir).unwrap();
+        assert!(store.remove(&small.timestamp.start_digest).unwrap());
+        assert!(!store.remove(&small.timestamp.start_digest).unwrap());
+        assert_eq!(store.load_all().unwrap(), vec![large]);
+
+        // A corrupt file is an error, not a missing timestamp
+        fs::write(store.path(&[0xcd; 32]), b"not a timestamp").unwrap();
+        assert!(matches!(store.get(&[0xcd; 32]).unwrap_err().root(), Error::Truncated));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merge() {
+        let dir = env::temp_dir().join(format!("ots-store-merge-{}", process::id()));
+        let store = TimestampStore::open(&dir).unwrap();
+        let full = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let mut bitcoin = full.clone();
+        bitcoin.timestamp.filter_attestations(|_, attest| matches!(*attest, Attestation::Bitcoin { .. })).unwrap();
+        let mut pending = full.clone();
+        pending.timestamp.filter_attestations(|_, attest| matches!(*attest, Attestation::Pending { .. })).unwrap();
+
+        // Merging a less complete proof keeps what was stored
+        assert_eq!(store.merge(bitcoin.clone()).unwrap(), bitcoin);
+        let merged = store.merge(pending).unwrap();
+        assert_eq!(merged.timestamp.attestations().count(), full.timestamp.attestations().count());
+        assert_eq!(store.get(&full.timestamp.start_digest).unwrap(), Some(merged.clone()));
+        assert_eq!(store.merge(bitcoin).unwrap(), merged);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Streaming
+//!
+//! Incremental processing of timestamps in their serialized order, for
+//! proofs too large to comfortably hold as a `Timestamp` tree. On the
+//! wire a timestamp is a flat sequence of steps: a fork marker means the
+//! following path ends in an attestation, after which parsing resumes
+//! from the digest at the fork. The proof ends at the attestation which
+//! leaves no forks open.
+//!
+//! `StepReader` parses this sequence and `StepWriter` produces it, each
+//! checking the step outputs and the fork structure as it goes.
+//!
+
+use std::io::{Read, Write};
+use std::mem;
+
+use error::Error;
+use op::Op;
+use attestation::Attestation;
+use ser;
+use timestamp::{StepData, RECURSION_LIMIT};
+
+/// A single step of a timestamp in serialized order, without successors
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FlatStep {
+    /// The contents of the step
+    pub data: StepData,
+    /// The digest after the step. For forks and attestations this is the
+    /// same as the digest before it.
+    pub output: Vec<u8>
+}
+
+/// A fork whose branches are still being read
+struct OpenFork {
+    /// The digest each branch starts from
+    digest: Vec<u8>,
+    /// Depth of the first step of each branch
+    depth: usize,
+    /// Number of branches started so far
+    branches: usize
+}
+
+/// Iterator which parses the steps of a timestamp one at a time. It
+/// enforces the same limits as `Timestamp::deserialize`, so accepts
+/// exactly the proofs that it does.
+pub struct StepReader<R: Read> {
+    deser: ser::Deserializer<R>,
+    digest: Vec<u8>,
+    depth: usize,
+    forks: Vec<OpenFork>,
+    /// The fork whose branch just ended, which continues with another
+    /// fork marker or else its last branch
+    resumed: Option<OpenFork>,
+    done: bool,
+    failed: bool
+}
+
+impl<R: Read> StepReader<R> {
+    /// Constructs a reader for the timestamp of `start_digest` which comes
+    /// next in `deser`
+    pub fn new(deser: ser::Deserializer<R>, start_digest: Vec<u8>) -> StepReader<R> {
+        StepReader {
+            deser,
+            digest: start_digest,
+            depth: 0,
+            forks: vec![],
+            resumed: None,
+            done: false,
+            failed: false
+        }
+    }
+
+    /// Whether the whole timestamp has been read
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Extracts the underlying deserializer, e.g. to check for EOF once
+    /// the timestamp is done
+    pub fn into_inner(self) -> ser::Deserializer<R> {
+        self.deser
+    }
+
+    /// Opens another branch of `fork`, checking the branch limit
+    fn open_branch(&mut self, mut fork: OpenFork) -> Result<FlatStep, Error> {
+        let max = self.deser.limits().max_fork_branches;
+        if fork.branches + 1 >= max {
+            return Err(Error::LimitExceeded { limit: "fork branches", max });
+        }
+        fork.branches += 1;
+        self.depth = fork.depth;
+        self.forks.push(fork);
+        Ok(FlatStep { data: StepData::Fork, output: self.digest.clone() })
+    }
+
+    fn read_step(&mut self) -> Result<FlatStep, Error> {
+        // After a branch ends, a fork marker continues the same fork
+        // rather than starting a nested one
+        let mut tag = None;
+        if let Some(fork) = self.resumed.take() {
+            match self.deser.read_byte()? {
+                0xff => return self.open_branch(fork),
+                next => tag = Some(next)
+            }
+        }
+
+        if self.depth >= RECURSION_LIMIT {
+            return Err(Error::StackOverflow);
+        }
+        self.deser.count_step()?;
+        let tag = match tag {
+            Some(tag) => tag,
+            None => self.deser.read_byte()?
+        };
+        match tag {
+            0x00 => {
+                let attest = Attestation::deserialize(&mut self.deser)?;
+                let output = match self.forks.pop() {
+                    Some(fork) => {
+                        self.depth = fork.depth;
+                        let output = mem::replace(&mut self.digest, fork.digest.clone());
+                        self.resumed = Some(fork);
+                        output
+                    }
+                    None => {
+                        self.done = true;
+                        self.digest.clone()
+                    }
+                };
+                Ok(FlatStep { data: StepData::Attestation(attest), output })
+            }
+            0xff => {
+                let fork = OpenFork { digest: self.digest.clone(), depth: self.depth + 1, branches: 0 };
+                self.open_branch(fork)
+            }
+            tag => {
+                let op = Op::deserialize_with_tag(&mut self.deser, tag)?;
+                let output = op.execute(&self.digest);
+                let max = self.deser.limits().max_result_length;
+                if output.len() > max {
+                    return Err(Error::BadLength { min: 0, max, val: output.len() });
+                }
+                self.digest = output.clone();
+                self.depth += 1;
+                Ok(FlatStep { data: StepData::Op(op), output })
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for StepReader<R> {
+    type Item = Result<FlatStep, Error>;
+
+    fn next(&mut self) -> Option<Result<FlatStep, Error>> {
+        if self.done || self.failed {
+            return None;
+        }
+        let ret = self.read_step();
+        if ret.is_err() {
+            self.failed = true;
+        }
+        Some(ret)
+    }
+}
+
+/// Serializer which writes the steps of a timestamp one at a time
+pub struct StepWriter<W: Write> {
+    ser: ser::Serializer<W>,
+    digest: Vec<u8>,
+    forks: Vec<Vec<u8>>,
+    max_result: usize,
+    done: bool
+}
+
+impl<W: Write> StepWriter<W> {
+    /// Constructs a writer for a timestamp of `start_digest`
+    pub fn new(ser: ser::Serializer<W>, start_digest: Vec<u8>) -> StepWriter<W> {
+        StepWriter::with_limits(ser, start_digest, &ser::Limits::default())
+    }
+
+    /// Constructs a writer which allows op results up to the length
+    /// given in `limits`, rather than the default
+    pub fn with_limits(ser: ser::Serializer<W>, start_digest: Vec<u8>, limits: &ser::Limits) -> StepWriter<W> {
+        StepWriter {
+            ser,
+            digest: start_digest,
+            forks: vec![],
+            max_result: limits.max_result_length,
+            done: false
+        }
+    }
+
+    /// Writes a single step, checking that its output follows from the
+    /// previous steps
+    pub fn write_step(&mut self, step: &FlatStep) -> Result<(), Error> {
+        if self.done {
+            return Err(Error::MalformedStep);
+        }
+        match step.data {
+            StepData::Fork => {
+                if step.output != self.digest {
+                    return Err(Error::OutputMismatch);
+                }
+                self.ser.write_byte(0xff)?;
+                self.forks.push(self.digest.clone());
+            }
+            StepData::Op(ref op) => {
+                let output = op.execute(&self.digest);
+                if step.output != output {
+                    return Err(Error::OutputMismatch);
+                }
+                if output.len() > self.max_result {
+                    return Err(Error::BadLength { min: 0, max: self.max_result, val: output.len() });
+                }
+                op.serialize(&mut self.ser)?;
+                self.digest = output;
+            }
+            StepData::Attestation(ref attest) => {
+                if step.output != self.digest {
+                    return Err(Error::OutputMismatch);
+                }
+                self.ser.write_byte(0x00)?;
+                attest.serialize(&mut self.ser)?;
+                match self.forks.pop() {
+                    Some(digest) => self.digest = digest,
+                    None => self.done = true
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every step from an iterator
+    pub fn write_steps<I: IntoIterator<Item = FlatStep>>(&mut self, steps: I) -> Result<(), Error> {
+        for step in steps {
+            self.write_step(&step)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the whole timestamp has been written
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Extracts the underlying serializer, failing if the timestamp is
+    /// incomplete
+    pub fn finish(self) -> Result<ser::Serializer<W>, Error> {
+        if self.done {
+            Ok(self.ser)
+        } else {
+            Err(Error::MalformedStep)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ser::DetachedTimestampFile;
+    use tests::{LARGE_TEST, SMALL_TEST};
+    use timestamp::{Step, Timestamp};
+    use super::*;
+
+    /// Flattens a timestamp tree into serialized order
+    fn flatten(step: &Step, out: &mut Vec<FlatStep>) {
+        match step.data {
+            StepData::Fork => {
+                for next in &step.next[..step.next.len() - 1] {
+                    out.push(FlatStep { data: StepData::Fork, output: step.output.clone() });
+                    flatten(next, out);
+                }
+                flatten(&step.next[step.next.len() - 1], out);
+            }
+            _ => {
+                out.push(FlatStep { data: step.data.clone(), output: step.output.clone() });
+                for next in &step.next {
+                    flatten(next, out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_tree() {
+        for data in &[SMALL_TEST, LARGE_TEST] {
+            let ots = DetachedTimestampFile::from_reader(*data).unwrap();
+            let mut expected = vec![];
+            flatten(&ots.timestamp.first_step, &mut expected);
+
+            // Skip the file header: magic, version, digest type and digest
+            let header_len = data.len() - {
+                let mut ser = ser::Serializer::new(vec![]);
+                ots.timestamp.serialize(&mut ser).unwrap();
+                ser.into_inner().len()
+            };
+            let deser = ser::Deserializer::new(&data[header_len..]);
+            let mut reader = StepReader::new(deser, ots.timestamp.start_digest.clone());
+            let steps: Vec<FlatStep> = reader.by_ref().map(Result::unwrap).collect();
+            assert_eq!(steps, expected);
+            assert!(reader.is_done());
+            reader.into_inner().check_eof().unwrap();
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let data = b"\x08\xff\x00\x05\x88\x96\x0d\x73\xd7\x19\x01\x01\x01";
+        let reader = StepReader::new(ser::Deserializer::new(&data[..]), vec![0; 32]);
+        let steps: Vec<_> = reader.collect();
+        assert_eq!(steps.len(), 4);
+        assert!(steps[..3].iter().all(Result::is_ok));
+        match steps[3] {
+            Err(Error::Truncated) => {}
+            ref x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    /// Parses a serialized timestamp both as a tree and as a stream,
+    /// checking that they agree on whether it is valid
+    fn parses(data: &[u8], limits: ser::Limits) -> bool {
+        let mut deser = ser::Deserializer::with_limits(data, limits);
+        let tree = Timestamp::deserialize(&mut deser, vec![0; 32]).is_ok();
+        let reader = StepReader::new(ser::Deserializer::with_limits(data, limits), vec![0; 32]);
+        let stream = reader.collect::<Result<Vec<_>, _>>().is_ok();
+        assert_eq!(tree, stream);
+        tree
+    }
+
+    #[test]
+    fn limits() {
+        let attest = b"\x00\x05\x88\x96\x0d\x73\xd7\x19\x01\x01\x01";
+
+        // Chains of ops
+        for &(n, ok) in &[(RECURSION_LIMIT - 1, true), (RECURSION_LIMIT, false)] {
+            let mut data = vec![0x08; n];
+            data.extend_from_slice(attest);
+            assert_eq!(parses(&data, ser::Limits::default()), ok);
+        }
+
+        // Forks nested in the first branch of each other
+        for &(n, ok) in &[(RECURSION_LIMIT - 1, true), (RECURSION_LIMIT, false)] {
+            let mut data = vec![0xff; n];
+            for _ in 0..n + 1 {
+                data.extend_from_slice(attest);
+            }
+            assert_eq!(parses(&data, ser::Limits::default()), ok);
+        }
+
+        // Wide forks, at the top level and nested
+        for &(branches, ok) in &[(2, true), (3, false)] {
+            let mut data = vec![];
+            for _ in 0..branches - 1 {
+                data.push(0xff);
+                data.extend_from_slice(attest);
+            }
+            data.extend_from_slice(attest);
+            let limits = ser::Limits { max_fork_branches: 2, ..ser::Limits::default() };
+            assert_eq!(parses(&data, limits), ok);
+
+            let mut nested = vec![0xff];
+            nested.extend_from_slice(&data);
+            nested.extend_from_slice(attest);
+            assert_eq!(parses(&nested, limits), ok);
+        }
+    }
+
+    #[test]
+    fn write_round_trip() {
+        for data in &[SMALL_TEST, LARGE_TEST] {
+            let ots = DetachedTimestampFile::from_reader(*data).unwrap();
+            let mut steps = vec![];
+            flatten(&ots.timestamp.first_step, &mut steps);
+
+            let mut ser = ser::Serializer::new(vec![]);
+            ots.timestamp.serialize(&mut ser).unwrap();
+            let expected = ser.into_inner();
+
+            let mut writer = StepWriter::new(ser::Serializer::new(vec![]), ots.timestamp.start_digest.clone());
+            writer.write_steps(steps.clone()).unwrap();
+            assert_eq!(writer.finish().unwrap().into_inner(), expected);
+
+            // Dropping the final attestation leaves the proof incomplete
+            let mut writer = StepWriter::new(ser::Serializer::new(vec![]), ots.timestamp.start_digest.clone());
+            writer.write_steps(steps[..steps.len() - 1].iter().cloned()).unwrap();
+            assert!(!writer.is_done());
+            assert!(writer.finish().is_err());
+
+            // Nothing may follow it either
+            let mut writer = StepWriter::new(ser::Serializer::new(vec![]), ots.timestamp.start_digest.clone());
+            writer.write_steps(steps.clone()).unwrap();
+            assert!(writer.write_step(&steps[0]).is_err());
+        }
+    }
+
+    #[test]
+    fn write_bad_output() {
+        let mut writer = StepWriter::new(ser::Serializer::new(vec![]), vec![0; 32]);
+        let step = FlatStep { data: StepData::Op(Op::Sha256), output: vec![0; 32] };
+        match writer.write_step(&step) {
+            Err(Error::OutputMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+}
@@ -16,7 +16,8 @@
 //!
 
 use std::fmt;
-use std::io::{Read, Write};
+use std::mem;
+use std::io::{self, Read, Write};
 
 use attestation::Attestation;
 use error::Error;
@@ -25,7 +26,7 @@ use op::Op;
 use ser;
 
 /// Anti-DoS
-const RECURSION_LIMIT: usize = 256;
+pub(crate) const RECURSION_LIMIT: usize = 256;
 
 /// The actual contents of the execution step
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -49,6 +50,76 @@ pub struct Step {
     pub next: Vec<Step>
 }
 
+/// Iterator over the attestations of a timestamp, in the order they
+/// appear in the proof, along with the commitment each attests to
+pub struct Attestations<'a> {
+    stack: Vec<&'a Step>
+}
+
+impl<'a> Iterator for Attestations<'a> {
+    type Item = (&'a [u8], &'a Attestation);
+
+    fn next(&mut self) -> Option<(&'a [u8], &'a Attestation)> {
+        while let Some(step) = self.stack.pop() {
+            match step.data {
+                StepData::Attestation(ref attest) => return Some((&step.output, attest)),
+                _ => self.stack.extend(step.next.iter().rev())
+            }
+        }
+        None
+    }
+}
+
+/// The ops leading from a timestamp's start digest to one of its
+/// attestations, with the intermediate result after each
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AttestationPath<'a> {
+    /// Each op along the path, with its output
+    pub ops: Vec<(&'a Op, &'a [u8])>,
+    /// The commitment attested to, i.e. the output of the last op
+    pub commitment: &'a [u8],
+    /// The attestation at the end of the path
+    pub attestation: &'a Attestation
+}
+
+/// Counts of the steps making up a timestamp
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Stats {
+    /// Number of ops
+    pub ops: usize,
+    /// Number of forks
+    pub forks: usize,
+    /// Number of Bitcoin attestations
+    pub bitcoin: usize,
+    /// Number of Ethereum attestations
+    pub ethereum: usize,
+    /// Number of pending attestations
+    pub pending: usize,
+    /// Number of attestations of unknown type
+    pub unknown: usize
+}
+
+impl Stats {
+    /// Total number of attestations
+    pub fn attestations(&self) -> usize {
+        self.bitcoin + self.ethereum + self.pending + self.unknown
+    }
+}
+
+/// Writer which discards its data, only counting the bytes
+struct ByteCount(usize);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 /// Main structure representing a timestamp
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Timestamp {
@@ -64,11 +135,14 @@ impl Timestamp {
         if recursion_limit == 0 {
             return Err(Error::StackOverflow);
         }
+        deser.count_step()?;
+        let index = deser.step_count();
+        let in_step = |kind| move |error| Error::InStep { index, kind, error: Box::new(error) };
 
         // Read next tag if we weren't given one
         let tag = match tag {
             Some(tag) => tag,
-            None => deser.read_byte()?
+            None => deser.read_byte().map_err(in_step("tag"))?
         };
 
         // A tag typically indicates an op to execute, but the two special values
@@ -77,7 +151,7 @@ impl Timestamp {
         match tag {
             // Attestation
             0x00 => {
-                let attest = Attestation::deserialize(deser)?;
+                let attest = Attestation::deserialize(deser).map_err(in_step("attestation"))?;
                 trace!("[{:3}] Attestation: {}", recursion_limit, attest);
                 Ok(Step {
                     data: StepData::Attestation(attest),
@@ -91,8 +165,12 @@ impl Timestamp {
                 let mut next_tag = 0xff;
                 while next_tag == 0xff {
                     trace!("[{:3}] Forking..", recursion_limit);
+                    if forks.len() + 1 >= deser.limits().max_fork_branches {
+                        let error = Error::LimitExceeded { limit: "fork branches", max: deser.limits().max_fork_branches };
+                        return Err(in_step("fork")(error));
+                    }
                     forks.push(Timestamp::deserialize_step_recurse(deser, input_digest.clone(), None, recursion_limit - 1)?);
-                    next_tag = deser.read_byte()?;
+                    next_tag = deser.read_byte().map_err(in_step("fork"))?;
                 }
                 forks.push(Timestamp::deserialize_step_recurse(deser, input_digest.clone(), Some(next_tag), recursion_limit - 1)?);
                 Ok(Step {
@@ -104,15 +182,19 @@ impl Timestamp {
             // An actual tag
             tag => {
                 // parse tag
-                let op = Op::deserialize_with_tag(deser, tag)?;
+                let op = Op::deserialize_with_tag(deser, tag).map_err(in_step("op"))?;
                 let output_digest = op.execute(&input_digest);
+                let max = deser.limits().max_result_length;
+                if output_digest.len() > max {
+                    return Err(in_step("op")(Error::BadLength { min: 0, max, val: output_digest.len() }));
+                }
                 trace!("[{:3}] Tag {} maps {} to {}.", recursion_limit, op, Hexed(&input_digest), Hexed(&output_digest));
                 // recurse
                 let next = vec![Timestamp::deserialize_step_recurse(deser, output_digest.clone(), None, recursion_limit - 1)?];
                 Ok(Step {
                     data: StepData::Op(op),
                     output: output_digest,
-                    next: next
+                    next
                 })
             }
         }
@@ -124,7 +206,7 @@ impl Timestamp {
 
         Ok(Timestamp {
             start_digest: digest,
-            first_step: first_step
+            first_step
         })
     }
 
@@ -152,6 +234,200 @@ impl Timestamp {
     pub fn serialize<W: Write>(&self, ser: &mut ser::Serializer<W>) -> Result<(), Error> {
         Timestamp::serialize_step_recurse(ser, &self.first_step)
     }
+
+    fn validate_step_recurse(step: &Step, input: &[u8], max_result: usize, scratch: &mut Vec<u8>) -> Result<(), Error> {
+        match step.data {
+            StepData::Fork => {
+                if step.next.len() < 2 {
+                    return Err(Error::MalformedStep);
+                }
+                if step.output != input {
+                    return Err(Error::OutputMismatch);
+                }
+                for fork in &step.next {
+                    Timestamp::validate_step_recurse(fork, input, max_result, scratch)?;
+                }
+                Ok(())
+            }
+            StepData::Op(ref op) => {
+                if step.next.len() != 1 {
+                    return Err(Error::MalformedStep);
+                }
+                op.execute_into(input, scratch);
+                if step.output != *scratch {
+                    return Err(Error::OutputMismatch);
+                }
+                if step.output.len() > max_result {
+                    return Err(Error::BadLength { min: 0, max: max_result, val: step.output.len() });
+                }
+                Timestamp::validate_step_recurse(&step.next[0], &step.output, max_result, scratch)
+            }
+            StepData::Attestation(_) => {
+                if !step.next.is_empty() {
+                    return Err(Error::MalformedStep);
+                }
+                if step.output != input {
+                    return Err(Error::OutputMismatch);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks that a timestamp is well-formed: forks have at least two
+    /// branches, ops have exactly one successor, attestations have none,
+    /// and every step's output is what executing it on its input gives,
+    /// and no longer than `MAX_RESULT_LENGTH`.
+    /// Timestamps produced by `deserialize` always pass; this is for ones
+    /// constructed or modified by hand.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.validate_with_limits(&ser::Limits::default())
+    }
+
+    /// Checks that a timestamp is well-formed, as `validate`, but with
+    /// the maximum op result length taken from `limits`
+    pub fn validate_with_limits(&self, limits: &ser::Limits) -> Result<(), Error> {
+        Timestamp::validate_step_recurse(&self.first_step, &self.start_digest, limits.max_result_length, &mut vec![])
+    }
+
+    /// The exact length of the serialized timestamp, not including the
+    /// start digest, computed without buffering it
+    pub fn serialized_size(&self) -> usize {
+        let mut ser = ser::Serializer::new(ByteCount(0));
+        self.serialize(&mut ser).expect("counting never fails");
+        ser.into_inner().0
+    }
+
+    /// Counts the ops, forks and attestations of each kind
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        let mut stack = vec![&self.first_step];
+        while let Some(step) = stack.pop() {
+            match step.data {
+                StepData::Fork => stats.forks += 1,
+                StepData::Op(_) => stats.ops += 1,
+                StepData::Attestation(Attestation::Bitcoin { .. }) => stats.bitcoin += 1,
+                StepData::Attestation(Attestation::Ethereum { .. }) => stats.ethereum += 1,
+                StepData::Attestation(Attestation::Pending { .. }) => stats.pending += 1,
+                StepData::Attestation(Attestation::Unknown { .. }) => stats.unknown += 1
+            }
+            stack.extend(&step.next);
+        }
+        stats
+    }
+
+    /// Iterates over every attestation, with the commitment it attests to.
+    /// Commitments are taken from the stored step outputs, which are
+    /// correct for deserialized timestamps; use `validate` on others.
+    pub fn attestations(&self) -> Attestations<'_> {
+        Attestations { stack: vec![&self.first_step] }
+    }
+
+    /// Lists the path to each attestation, in the order `attestations`
+    /// gives them, with every intermediate digest along the way
+    pub fn attestation_paths(&self) -> Vec<AttestationPath<'_>> {
+        let mut ret = vec![];
+        paths_recurse(&self.first_step, &mut vec![], &mut ret);
+        ret
+    }
+
+    /// Extends the timestamp backwards to start from an earlier digest,
+    /// e.g. to turn a proof of `sha256(file)` into a proof of the file.
+    /// Executing `ops` on `start_digest` must produce this timestamp's
+    /// start digest.
+    pub fn compose(self, start_digest: Vec<u8>, ops: &[Op]) -> Result<Timestamp, Error> {
+        let mut outputs = Vec::with_capacity(ops.len());
+        let mut output = start_digest.clone();
+        for op in ops {
+            output = op.execute(&output);
+            outputs.push(output.clone());
+        }
+        if output != self.start_digest {
+            return Err(Error::OutputMismatch);
+        }
+
+        let mut first_step = self.first_step;
+        for (op, output) in ops.iter().zip(outputs).rev() {
+            first_step = Step {
+                data: StepData::Op(op.clone()),
+                output,
+                next: vec![first_step]
+            };
+        }
+        Ok(Timestamp { start_digest, first_step })
+    }
+
+    /// Merges another timestamp of the same digest into this one, so that
+    /// the result has the attestations of both. Ops common to the start of
+    /// both branches are shared rather than duplicated.
+    pub fn merge(&mut self, other: Timestamp) -> Result<(), Error> {
+        if other.start_digest != self.start_digest {
+            return Err(Error::CommitmentMismatch);
+        }
+        merge_recurse(&mut self.first_step, other.first_step, &self.start_digest);
+        Ok(())
+    }
+
+    /// Removes pending attestations which have become redundant: those
+    /// directly under a fork where another branch reaches a Bitcoin
+    /// attestation, as happens after `calendar::upgrade`. Forks left with
+    /// a single branch are collapsed. Returns whether anything was removed.
+    pub fn prune_pending(&mut self) -> bool {
+        prune_pending_recurse(&mut self.first_step)
+    }
+
+    /// Removes every attestation for which `keep`, given the attestation
+    /// and its commitment, returns false, along with the ops leading only
+    /// to it. Forks left with a single branch are collapsed. Fails, leaving
+    /// the timestamp unchanged, if no attestations would remain. Returns
+    /// whether anything was removed.
+    pub fn filter_attestations<F>(&mut self, mut keep: F) -> Result<bool, Error>
+        where F: FnMut(&[u8], &Attestation) -> bool
+    {
+        let mut first_step = self.first_step.clone();
+        let mut changed = false;
+        if !filter_recurse(&mut first_step, &mut keep, &mut changed) {
+            return Err(Error::NoAttestations);
+        }
+        self.first_step = first_step;
+        Ok(changed)
+    }
+
+    /// Reduces the timestamp to an equivalent smaller one: repeated
+    /// attestations of the same commitment are dropped, nested forks are
+    /// flattened and branches starting with the same op are merged.
+    /// Branches which are strictly dominated are removed too, i.e. those
+    /// with only pending attestations beside a branch with a Bitcoin
+    /// attestation, so the result is at least as strong as the original
+    /// but may have fewer attestations. Returns whether anything changed.
+    pub fn minimize(&mut self) -> bool {
+        let before = self.first_step.clone();
+        let mut seen: Vec<(Vec<u8>, Attestation)> = vec![];
+        filter_recurse(&mut self.first_step, &mut |commitment: &[u8], attest: &Attestation| {
+            if seen.iter().any(|(c, a)| c[..] == *commitment && a == attest) {
+                false
+            } else {
+                seen.push((commitment.to_vec(), attest.clone()));
+                true
+            }
+        }, &mut false);
+        minimize_recurse(&mut self.first_step, &self.start_digest);
+        drop_dominated_recurse(&mut self.first_step);
+        self.first_step != before
+    }
+
+    /// Renders the timestamp as a Graphviz graph: intermediate digests
+    /// are nodes, ops label the edges between them, and attestations
+    /// are boxed leaves
+    pub fn to_dot(&self) -> String {
+        let mut ret = String::new();
+        ret.push_str("digraph timestamp {\n");
+        ret.push_str(&format!("    n0 [label=\"{}\"];\n", Hexed(&self.start_digest)));
+        let mut next_id = 1;
+        dot_recurse(&self.first_step, 0, &mut next_id, &mut ret);
+        ret.push_str("}\n");
+        ret
+    }
 }
 
 fn fmt_recurse(step: &Step, f: &mut fmt::Formatter, depth: usize, first_line: bool) -> fmt::Result {
@@ -194,6 +470,171 @@ fn fmt_recurse(step: &Step, f: &mut fmt::Formatter, depth: usize, first_line: bo
     }
 }
 
+/// Collects the non-fork steps reachable through nested forks
+fn flatten_forks(step: Step, out: &mut Vec<Step>) {
+    match step.data {
+        StepData::Fork => for next in step.next {
+            flatten_forks(next, out);
+        },
+        _ => out.push(step)
+    }
+}
+
+fn merge_recurse(step: &mut Step, other: Step, input: &[u8]) {
+    // Forks all compare equal, so match on their flattened branches
+    let placeholder = Step { data: StepData::Fork, output: vec![], next: vec![] };
+    let mut branches = vec![];
+    flatten_forks(mem::replace(step, placeholder), &mut branches);
+    let mut others = vec![];
+    flatten_forks(other, &mut others);
+
+    for other in others {
+        match branches.iter().position(|branch| branch.data == other.data) {
+            Some(idx) => if let StepData::Op(_) = other.data {
+                let next = other.next.into_iter().next().expect("op has successor");
+                let branch = &mut branches[idx];
+                merge_recurse(&mut branch.next[0], next, &branch.output);
+            },
+            None => branches.push(other)
+        }
+    }
+
+    *step = if branches.len() == 1 {
+        branches.pop().expect("one branch")
+    } else {
+        Step { data: StepData::Fork, output: input.to_vec(), next: branches }
+    };
+}
+
+fn paths_recurse<'a>(step: &'a Step, ops: &mut Vec<(&'a Op, &'a [u8])>, out: &mut Vec<AttestationPath<'a>>) {
+    match step.data {
+        StepData::Fork => {
+            for next in &step.next {
+                paths_recurse(next, ops, out);
+            }
+        }
+        StepData::Op(ref op) => {
+            ops.push((op, &step.output));
+            paths_recurse(&step.next[0], ops, out);
+            ops.pop();
+        }
+        StepData::Attestation(ref attestation) => out.push(AttestationPath {
+            ops: ops.clone(),
+            commitment: &step.output,
+            attestation
+        })
+    }
+}
+
+fn minimize_recurse(step: &mut Step, input: &[u8]) {
+    match step.data {
+        StepData::Fork => {
+            let mut branches = step.next.split_off(0).into_iter();
+            let mut first = branches.next().expect("fork has successors");
+            minimize_recurse(&mut first, input);
+            for mut branch in branches {
+                minimize_recurse(&mut branch, input);
+                merge_recurse(&mut first, branch, input);
+            }
+            *step = first;
+        }
+        StepData::Op(_) => minimize_recurse(&mut step.next[0], &step.output),
+        StepData::Attestation(_) => {}
+    }
+}
+
+/// Drops the branches of each fork which have only pending attestations,
+/// if another branch has a Bitcoin attestation
+fn drop_dominated_recurse(step: &mut Step) {
+    for next in &mut step.next {
+        drop_dominated_recurse(next);
+    }
+
+    if let StepData::Fork = step.data {
+        let has_bitcoin = |next: &Step| {
+            Attestations { stack: vec![next] }.any(|(_, attest)| matches!(*attest, Attestation::Bitcoin { .. }))
+        };
+        if step.next.iter().any(has_bitcoin) {
+            step.next.retain(|next| {
+                Attestations { stack: vec![next] }.any(|(_, attest)| !matches!(*attest, Attestation::Pending { .. }))
+            });
+            if step.next.len() == 1 {
+                *step = step.next.pop().expect("one branch");
+            }
+        }
+    }
+}
+
+/// Filters the attestations under a step, returning whether any remain
+fn filter_recurse<F>(step: &mut Step, keep: &mut F, changed: &mut bool) -> bool
+    where F: FnMut(&[u8], &Attestation) -> bool
+{
+    match step.data {
+        StepData::Fork => {
+            step.next.retain_mut(|next| filter_recurse(next, keep, changed));
+            match step.next.len() {
+                0 => false,
+                1 => {
+                    *step = step.next.pop().expect("one branch");
+                    true
+                }
+                _ => true
+            }
+        }
+        StepData::Op(_) => filter_recurse(&mut step.next[0], keep, changed),
+        StepData::Attestation(ref attest) => {
+            let ret = keep(&step.output, attest);
+            *changed |= !ret;
+            ret
+        }
+    }
+}
+
+fn prune_pending_recurse(step: &mut Step) -> bool {
+    let mut changed = false;
+    for next in &mut step.next {
+        changed |= prune_pending_recurse(next);
+    }
+
+    if let StepData::Fork = step.data {
+        let has_bitcoin = step.next.iter().any(|next| {
+            Attestations { stack: vec![next] }.any(|(_, attest)| matches!(*attest, Attestation::Bitcoin { .. }))
+        });
+        if has_bitcoin {
+            let len = step.next.len();
+            step.next.retain(|next| !matches!(next.data, StepData::Attestation(Attestation::Pending { .. })));
+            changed |= step.next.len() != len;
+            if step.next.len() == 1 {
+                *step = step.next.pop().expect("one branch");
+            }
+        }
+    }
+    changed
+}
+
+fn dot_recurse(step: &Step, input: usize, next_id: &mut usize, out: &mut String) {
+    let id = *next_id;
+    match step.data {
+        StepData::Fork => {
+            for fork in &step.next {
+                dot_recurse(fork, input, next_id, out);
+            }
+        }
+        StepData::Op(ref op) => {
+            *next_id += 1;
+            out.push_str(&format!("    n{} [label=\"{}\"];\n", id, Hexed(&step.output)));
+            out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", input, id, op));
+            dot_recurse(&step.next[0], id, next_id, out);
+        }
+        StepData::Attestation(ref attest) => {
+            *next_id += 1;
+            let label = format!("{}", attest).replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str(&format!("    n{} [shape=box, label=\"{}\"];\n", id, label));
+            out.push_str(&format!("    n{} -> n{};\n", input, id));
+        }
+    }
+}
+
 impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Starting digest: {}", Hexed(&self.start_digest))?;
@@ -201,3 +642,378 @@ impl fmt::Display for Timestamp {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use attestation::Attestation;
+    use op::Op;
+    use ser::DetachedTimestampFile;
+    use tests::{LARGE_TEST, SMALL_TEST};
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        assert!(ots.timestamp.validate().is_ok());
+
+        let mut bad = ots.timestamp.clone();
+        bad.start_digest[0] ^= 1;
+        match bad.validate() {
+            Err(Error::OutputMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        let mut bad = ots.timestamp.clone();
+        bad.first_step.next.push(bad.first_step.next[0].clone());
+        match bad.validate() {
+            Err(Error::MalformedStep) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn result_too_long() {
+        // Append 4064 bytes to a 32-byte digest, then attest
+        let mut data = vec![0xf0, 0xe0, 0x1f];
+        data.extend(vec![0xab; 4064]);
+        data.extend(b"\x00\x05\x88\x96\x0d\x73\xd7\x19\x01\x01\x01");
+        let mut deser = ser::Deserializer::new(&data[..]);
+        let ts = Timestamp::deserialize(&mut deser, vec![0; 32]).unwrap();
+        assert!(ts.validate().is_ok());
+
+        // One byte more is too long
+        data[1] = 0xe1;
+        data.insert(3, 0xab);
+        let mut deser = ser::Deserializer::new(&data[..]);
+        match Timestamp::deserialize(&mut deser, vec![0; 32]) {
+            Err(ref e) if matches!(e.root(), Error::BadLength { max: 4096, val: 4097, .. }) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // ...unless the limit is relaxed
+        let limits = ser::Limits { max_result_length: 4097, ..Default::default() };
+        let mut deser = ser::Deserializer::with_limits(&data[..], limits);
+        let ts = Timestamp::deserialize(&mut deser, vec![0; 32]).unwrap();
+        assert!(ts.validate().is_err());
+        assert!(ts.validate_with_limits(&limits).is_ok());
+
+        // A tighter limit rejects the 32-byte digests themselves
+        let limits = ser::Limits { max_result_length: 31, ..Default::default() };
+        let ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        assert!(ots.timestamp.validate_with_limits(&limits).is_err());
+    }
+
+    #[test]
+    fn attestations() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let attestations: Vec<_> = ots.timestamp.attestations().collect();
+        assert_eq!(attestations.len(), 4);
+        let heights: Vec<_> = attestations.iter().filter_map(|&(commitment, attest)| match *attest {
+            Attestation::Bitcoin { height } => {
+                assert_eq!(commitment.len(), 32);
+                Some(height)
+            }
+            _ => None
+        }).collect();
+        assert_eq!(heights, vec![449399, 449397]);
+    }
+
+    #[test]
+    fn attestation_paths() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let paths = ots.timestamp.attestation_paths();
+        let attestations: Vec<_> = ots.timestamp.attestations().collect();
+        assert_eq!(paths.len(), attestations.len());
+        for (path, &(commitment, attestation)) in paths.iter().zip(&attestations) {
+            assert_eq!(path.commitment, commitment);
+            assert_eq!(path.attestation, attestation);
+            // Replaying the ops reproduces each intermediate digest
+            let mut digest = ots.timestamp.start_digest.clone();
+            for &(op, output) in &path.ops {
+                digest = op.execute(&digest);
+                assert_eq!(digest, output);
+            }
+            assert_eq!(digest, commitment);
+        }
+    }
+
+    #[test]
+    fn stats() {
+        for data in &[SMALL_TEST, LARGE_TEST] {
+            let ots = DetachedTimestampFile::from_reader(*data).unwrap();
+            let mut ser = ser::Serializer::new(vec![]);
+            ots.timestamp.serialize(&mut ser).unwrap();
+            assert_eq!(ots.timestamp.serialized_size(), ser.into_inner().len());
+        }
+
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let stats = ots.timestamp.stats();
+        assert_eq!(stats.attestations(), ots.timestamp.attestations().count());
+        assert_eq!(stats.bitcoin, 2);
+        assert_eq!(stats.pending, 2);
+        assert_eq!(stats.forks, 3);
+    }
+
+    #[test]
+    fn minimize() {
+        let ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let mut ts = ots.timestamp.clone();
+        assert!(!ts.minimize());
+        assert_eq!(ts, ots.timestamp);
+
+        // The pending attestations of the large proof are beside its
+        // Bitcoin ones, so are dominated by them
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let mut ts = ots.timestamp.clone();
+        assert!(ts.minimize());
+        assert!(ts.validate().is_ok());
+        let mut bitcoin_only = ots.timestamp.clone();
+        bitcoin_only.filter_attestations(|_, attest| !matches!(*attest, Attestation::Pending { .. })).unwrap();
+        assert_eq!(ts, bitcoin_only);
+        assert!(!ts.minimize());
+
+        // Fork the whole proof against two copies of itself, with nested forks
+        let mut ts = ots.timestamp.clone();
+        let dup = Step {
+            data: StepData::Fork,
+            output: ts.start_digest.clone(),
+            next: vec![ts.first_step.clone(), ts.first_step.clone()]
+        };
+        ts.first_step = Step {
+            data: StepData::Fork,
+            output: ts.start_digest.clone(),
+            next: vec![dup, ts.first_step.clone()]
+        };
+        assert!(ts.validate().is_ok());
+        assert_eq!(ts.stats().attestations(), 12);
+        assert!(ts.minimize());
+        assert!(ts.validate().is_ok());
+        assert_eq!(ts, bitcoin_only);
+
+        // A pending-only branch behind its own op, beside a Bitcoin branch
+        let leaf = |op: Op, attest: Attestation| {
+            let output = op.execute(&ts.start_digest);
+            Step {
+                data: StepData::Op(op),
+                output: output.clone(),
+                next: vec![Step { data: StepData::Attestation(attest), output, next: vec![] }]
+            }
+        };
+        let pending = leaf(Op::Append(vec![1]), Attestation::Pending { uri: "https://a.example.com".to_owned() });
+        let bitcoin = leaf(Op::Sha256, Attestation::Bitcoin { height: 1 });
+        let ethereum = leaf(Op::Sha256, Attestation::Ethereum { height: 1 });
+        ts.first_step = Step { data: StepData::Fork, output: ts.start_digest.clone(), next: vec![pending.clone(), bitcoin.clone()] };
+        assert!(ts.minimize());
+        assert_eq!(ts.first_step, bitcoin);
+
+        // Other attestations do not dominate pending ones
+        ts.first_step = Step { data: StepData::Fork, output: ts.start_digest.clone(), next: vec![pending, ethereum] };
+        assert!(!ts.minimize());
+    }
+
+    #[test]
+    fn prune_pending() {
+        let pending = |output: &[u8], uri: &str| Step {
+            data: StepData::Attestation(Attestation::Pending { uri: uri.to_owned() }),
+            output: output.to_vec(),
+            next: vec![]
+        };
+        let start = vec![0x12; 32];
+        let hashed = Op::Sha256.execute(&start);
+        // fork(pending A, pending B, sha256 -> bitcoin), as left by two upgrades
+        let mut ts = Timestamp {
+            start_digest: start.clone(),
+            first_step: Step {
+                data: StepData::Fork,
+                output: start.clone(),
+                next: vec![
+                    pending(&start, "https://a.example.com"),
+                    pending(&start, "https://b.example.com"),
+                    Step {
+                        data: StepData::Op(Op::Sha256),
+                        output: hashed.clone(),
+                        next: vec![Step {
+                            data: StepData::Attestation(Attestation::Bitcoin { height: 1 }),
+                            output: hashed.clone(),
+                            next: vec![]
+                        }]
+                    }
+                ]
+            }
+        };
+        let original = ts.clone();
+        assert!(ts.prune_pending());
+        assert!(ts.validate().is_ok());
+        assert_eq!(ts.first_step, original.first_step.next[2]);
+        assert!(!ts.prune_pending());
+
+        // Without a Bitcoin attestation nothing is redundant
+        let ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let mut pruned = ots.timestamp.clone();
+        assert!(!pruned.prune_pending());
+        assert_eq!(pruned, ots.timestamp);
+    }
+
+    #[test]
+    fn filter_attestations() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+
+        let mut ts = ots.timestamp.clone();
+        assert!(!ts.filter_attestations(|_, _| true).unwrap());
+        assert_eq!(ts, ots.timestamp);
+
+        assert!(ts.filter_attestations(|_, attest| matches!(*attest, Attestation::Bitcoin { .. })).unwrap());
+        assert!(ts.validate().is_ok());
+        let remaining: Vec<_> = ts.attestations().map(|(_, attest)| attest.clone()).collect();
+        assert_eq!(remaining, vec![Attestation::Bitcoin { height: 449399 }, Attestation::Bitcoin { height: 449397 }]);
+
+        assert!(ts.filter_attestations(|_, attest| *attest == Attestation::Bitcoin { height: 449397 }).unwrap());
+        assert!(ts.validate().is_ok());
+        assert_eq!(ts.attestations().count(), 1);
+
+        match ts.filter_attestations(|_, _| false) {
+            Err(Error::NoAttestations) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+        assert_eq!(ts.attestations().count(), 1);
+    }
+
+    #[test]
+    fn compose() {
+        let ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        let ts = ots.timestamp;
+
+        // No ops is the identity
+        assert_eq!(ts.clone().compose(ts.start_digest.clone(), &[]).unwrap(), ts);
+
+        // Reversing twice gets back to the original digest
+        let reversed: Vec<u8> = ts.start_digest.iter().cloned().rev().collect();
+        let composed = ts.clone().compose(ts.start_digest.clone(), &[Op::Reverse, Op::Reverse]).unwrap();
+        assert!(composed.validate().is_ok());
+        assert_eq!(composed.first_step.output, reversed);
+        assert_eq!(composed.first_step.next[0].next[0], ts.first_step);
+        assert_eq!(composed.attestations().count(), ts.attestations().count());
+
+        // Ops which do not lead to the start digest
+        match ts.clone().compose(b"some message".to_vec(), &[Op::Sha256]) {
+            Err(Error::OutputMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let full = ots.timestamp;
+        let mut bitcoin = full.clone();
+        bitcoin.filter_attestations(|_, attest| matches!(*attest, Attestation::Bitcoin { .. })).unwrap();
+        let mut pending = full.clone();
+        pending.filter_attestations(|_, attest| matches!(*attest, Attestation::Pending { .. })).unwrap();
+
+        let mut merged = bitcoin.clone();
+        merged.merge(pending.clone()).unwrap();
+        assert!(merged.validate().is_ok());
+        assert_eq!(merged.attestations().count(), full.attestations().count());
+        for (commitment, attest) in full.attestations() {
+            assert!(merged.attestations().any(|x| x == (commitment, attest)));
+        }
+
+        // Merging is idempotent
+        let mut again = merged.clone();
+        again.merge(bitcoin).unwrap();
+        again.merge(pending).unwrap();
+        assert_eq!(again, merged);
+
+        let other = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        match merged.merge(other.timestamp) {
+            Err(Error::CommitmentMismatch) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    #[test]
+    fn merge_nested_forks() {
+        let digest = vec![0x55; 32];
+        let pending = |uri: &str| Step {
+            data: StepData::Attestation(Attestation::Pending { uri: uri.to_owned() }),
+            output: digest.clone(),
+            next: vec![]
+        };
+        let fork = |next| Step { data: StepData::Fork, output: digest.clone(), next };
+
+        // Fork[A, Fork[B, C]] merged with Fork[D, Fork[E, F]]
+        let mut ts = Timestamp {
+            start_digest: digest.clone(),
+            first_step: fork(vec![pending("a"), fork(vec![pending("b"), pending("c")])])
+        };
+        let other = Timestamp {
+            start_digest: digest.clone(),
+            first_step: fork(vec![pending("d"), fork(vec![pending("e"), pending("f")])])
+        };
+        ts.merge(other).unwrap();
+        assert!(ts.validate().is_ok());
+        let mut uris: Vec<String> = ts.attestations().map(|(_, attest)| match *attest {
+            Attestation::Pending { ref uri } => uri.clone(),
+            _ => panic!("unexpected attestation {}", attest)
+        }).collect();
+        uris.sort();
+        assert_eq!(uris, ["a", "b", "c", "d", "e", "f"]);
+    }
+
+    #[test]
+    fn display() {
+        let start = vec![0x12, 0x34];
+        let hashed = Op::Ripemd160.execute(&start);
+        let appended = Op::Append(vec![0xab]).execute(&hashed);
+        let ts = Timestamp {
+            start_digest: start,
+            first_step: Step {
+                data: StepData::Op(Op::Ripemd160),
+                output: hashed.clone(),
+                next: vec![Step {
+                    data: StepData::Fork,
+                    output: hashed.clone(),
+                    next: vec![
+                        Step {
+                            data: StepData::Attestation(Attestation::Bitcoin { height: 100 }),
+                            output: hashed.clone(),
+                            next: vec![]
+                        },
+                        Step {
+                            data: StepData::Op(Op::Append(vec![0xab])),
+                            output: appended.clone(),
+                            next: vec![Step {
+                                data: StepData::Attestation(Attestation::Pending { uri: "https://example.com".to_owned() }),
+                                output: appended.clone(),
+                                next: vec![]
+                            }]
+                        }
+                    ]
+                }]
+            }
+        };
+        assert!(ts.validate().is_ok());
+        assert_eq!(ts.to_dot(), format!("\
+digraph timestamp {{
+    n0 [label=\"1234\"];
+    n1 [label=\"{}\"];
+    n0 -> n1 [label=\"RIPEMD160()\"];
+    n2 [shape=box, label=\"Bitcoin block 100\"];
+    n1 -> n2;
+    n3 [label=\"{}\"];
+    n1 -> n3 [label=\"Append(ab)\"];
+    n4 [shape=box, label=\"Pending: update URI https://example.com\"];
+    n3 -> n4;
+}}
+", Hexed(&hashed), Hexed(&appended)));
+        assert_eq!(format!("{}", ts), format!("\
+Starting digest: 1234
+execute RIPEMD160()
+ result {}
+(fork 2 ways)
+--->result attested by Bitcoin block 100
+--->execute Append(ab)
+     result {}
+    result attested by Pending: update URI https://example.com
+", Hexed(&hashed), Hexed(&appended)));
+    }
+}
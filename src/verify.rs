@@ -0,0 +1,466 @@
+// OpenTimestamps Library
+// Written in 2017 by
+//   Andrew Poelstra <rust-ots@wpsoftware.net>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the CC0 Public Domain Dedication
+// along with this software.
+// If not, see <http://creativecommons.org/publicdomain/zero/1.0/>.
+//
+
+//! # Verification
+//!
+//! Checks the attestations of a timestamp against the Bitcoin blockchain.
+//! A Bitcoin attestation claims that the commitment reached at that point
+//! of the proof is the Merkle root of the block at some height, so to check
+//! it we need that block's header. Headers are supplied by an implementation
+//! of `BlockHeaderSource`, so different backends (a local node, a header
+//! file, a block explorer) can be plugged in.
+//!
+//! Attestation types unknown to this library can be checked too, by
+//! registering an `AttestationVerifier` for their tag in an
+//! `AttestationRegistry`. They are still deserialized as
+//! `Attestation::Unknown`, since that enum is closed, but a registry can
+//! check their payloads with `parse` as soon as a proof is loaded.
+//!
+
+use crypto::digest::Digest;
+
+use attestation::{self, Attestation};
+use error::Error;
+use hex::Hexed;
+use op::Sha256;
+use ser::Limits;
+use timestamp::{Step, StepData, Timestamp};
+
+/// Size in bytes of a serialized Bitcoin block header
+pub const HEADER_LEN: usize = 80;
+
+/// A Bitcoin block header
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlockHeader {
+    /// Block version
+    pub version: i32,
+    /// Hash of the previous block, in internal byte order
+    pub prev_blockhash: [u8; 32],
+    /// Merkle root of the block's transactions, in internal byte order
+    pub merkle_root: [u8; 32],
+    /// Block time, as claimed by the miner
+    pub time: u32,
+    /// Compact encoding of the proof-of-work target
+    pub bits: u32,
+    /// Proof-of-work nonce
+    pub nonce: u32
+}
+
+impl BlockHeader {
+    /// Parses a header from its 80-byte consensus encoding
+    pub fn from_bytes(data: &[u8]) -> Result<BlockHeader, Error> {
+        if data.len() != HEADER_LEN {
+            return Err(Error::BadLength { min: HEADER_LEN, max: HEADER_LEN, val: data.len() });
+        }
+
+        let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let mut prev_blockhash = [0; 32];
+        prev_blockhash.copy_from_slice(&data[4..36]);
+        let mut merkle_root = [0; 32];
+        merkle_root.copy_from_slice(&data[36..68]);
+
+        Ok(BlockHeader {
+            version: u32_at(0) as i32,
+            prev_blockhash,
+            merkle_root,
+            time: u32_at(68),
+            bits: u32_at(72),
+            nonce: u32_at(76)
+        })
+    }
+
+    /// Serializes the header in its 80-byte consensus encoding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(HEADER_LEN);
+        ret.extend(&(self.version as u32).to_le_bytes());
+        ret.extend(&self.prev_blockhash);
+        ret.extend(&self.merkle_root);
+        ret.extend(&self.time.to_le_bytes());
+        ret.extend(&self.bits.to_le_bytes());
+        ret.extend(&self.nonce.to_le_bytes());
+        ret
+    }
+
+    /// The block hash (double-SHA256 of the header), in internal byte order
+    pub fn block_hash(&self) -> [u8; 32] {
+        let mut ret = [0; 32];
+        let mut hasher = Sha256::new();
+        hasher.input(&self.to_bytes());
+        hasher.result(&mut ret);
+        hasher.reset();
+        hasher.input(&ret);
+        hasher.result(&mut ret);
+        ret
+    }
+}
+
+/// A source of Bitcoin block headers to verify attestations against
+pub trait BlockHeaderSource {
+    /// Returns the header of the block at the given height on the best chain
+    fn block_header(&self, height: usize) -> Result<BlockHeader, Error>;
+}
+
+/// A Bitcoin attestation which was successfully verified
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct VerificationResult {
+    /// Height of the attesting block
+    pub height: usize,
+    /// Hash of the attesting block, in internal byte order
+    pub block_hash: [u8; 32],
+    /// Unix time of the attesting block, as claimed by its miner
+    pub time: u32
+}
+
+/// The outcome of verifying a whole timestamp
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VerificationReport {
+    /// Every verified attestation, in the order they appear in the proof.
+    /// Never empty.
+    pub attestations: Vec<VerificationResult>
+}
+
+impl VerificationReport {
+    /// The attestation with the earliest block time. Note that block
+    /// times are not monotonic, so this need not be the lowest block.
+    pub fn earliest(&self) -> &VerificationResult {
+        self.attestations.iter().min_by_key(|r| (r.time, r.height)).expect("report is nonempty")
+    }
+
+    /// The earliest time at which the timestamped data is proven to exist
+    pub fn earliest_time(&self) -> u32 {
+        self.earliest().time
+    }
+}
+
+/// Something which can check an attestation type unknown to this library.
+/// Failures should be reported as `Error::BadAttestation`.
+pub trait AttestationVerifier {
+    /// Checks that an attestation's payload is well-formed, without
+    /// verifying it. The default accepts any payload.
+    fn parse(&self, _payload: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Checks an attestation's payload against the commitment it attests
+    /// to. Attestations which are not from a blockchain should report
+    /// a height of zero and an all-zero block hash.
+    fn verify(&self, commitment: &[u8], payload: &[u8]) -> Result<VerificationResult, Error>;
+}
+
+struct RegistryEntry {
+    tag: Vec<u8>,
+    verifier: Box<dyn AttestationVerifier>
+}
+
+/// Verifiers for custom attestation types, keyed by their 8-byte tag. Only
+/// attestations parsed as `Attestation::Unknown` are looked up, so tags
+/// this library understands cannot be overridden.
+#[derive(Default)]
+pub struct AttestationRegistry {
+    entries: Vec<RegistryEntry>
+}
+
+impl AttestationRegistry {
+    /// Constructs an empty registry
+    pub fn new() -> AttestationRegistry {
+        AttestationRegistry::default()
+    }
+
+    /// Registers a verifier for attestations with the given tag, replacing
+    /// any previously registered for it. The name is registered with
+    /// `attestation::register_tag_name`, for displaying them.
+    pub fn register<V: AttestationVerifier + 'static>(&mut self, tag: &[u8], name: &str, verifier: V) {
+        attestation::register_tag_name(tag, name);
+        self.entries.retain(|entry| entry.tag != tag);
+        self.entries.push(RegistryEntry {
+            tag: tag.to_vec(),
+            verifier: Box::new(verifier)
+        });
+    }
+
+    /// Runs the registered parser over the payload of every unknown
+    /// attestation in a timestamp which has one, e.g. right after the
+    /// timestamp is deserialized
+    pub fn parse(&self, timestamp: &Timestamp) -> Result<(), Error> {
+        for (_, attest) in timestamp.attestations() {
+            if let Attestation::Unknown { ref tag, ref data } = *attest {
+                if let Some(entry) = self.entry(tag) {
+                    entry.verifier.parse(data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(&self, tag: &[u8]) -> Option<&RegistryEntry> {
+        self.entries.iter().find(|entry| entry.tag == tag)
+    }
+}
+
+/// Verifies every Bitcoin attestation in a timestamp. Ops are re-executed
+/// from the start digest rather than trusting the stored step outputs.
+/// Fails if any Bitcoin attestation does not match its block, or if there
+/// are no Bitcoin attestations at all; other attestations are ignored.
+pub fn verify<S: BlockHeaderSource + ?Sized>(timestamp: &Timestamp, source: &S) -> Result<VerificationReport, Error> {
+    verify_with_registry(timestamp, source, &AttestationRegistry::new())
+}
+
+/// Like `verify`, but also parses and checks unknown attestations whose
+/// tag has a verifier in `registry`. Their results are included in the
+/// report, and it suffices for one of them to verify.
+pub fn verify_with_registry<S: BlockHeaderSource + ?Sized>(timestamp: &Timestamp, source: &S, registry: &AttestationRegistry) -> Result<VerificationReport, Error> {
+    verify_with_limits(timestamp, source, registry, &Limits::default())
+}
+
+/// Like `verify_with_registry`, but with the maximum op result length
+/// taken from `limits`. Fails with `Error::MalformedStep` if a step has
+/// the wrong number of successors, as `Timestamp::validate` would.
+pub fn verify_with_limits<S: BlockHeaderSource + ?Sized>(timestamp: &Timestamp, source: &S, registry: &AttestationRegistry, limits: &Limits) -> Result<VerificationReport, Error> {
+    let mut attestations = vec![];
+    verify_recurse(&timestamp.first_step, &timestamp.start_digest, source, registry, limits, &mut attestations)?;
+    if attestations.is_empty() {
+        Err(Error::NoVerifiableAttestation)
+    } else {
+        Ok(VerificationReport { attestations })
+    }
+}
+
+fn verify_recurse<S: BlockHeaderSource + ?Sized>(step: &Step, input: &[u8], source: &S, registry: &AttestationRegistry, limits: &Limits, results: &mut Vec<VerificationResult>) -> Result<(), Error> {
+    let successors_ok = match step.data {
+        StepData::Fork => step.next.len() >= 2,
+        StepData::Op(_) => step.next.len() == 1,
+        StepData::Attestation(_) => step.next.is_empty()
+    };
+    if !successors_ok {
+        return Err(Error::MalformedStep);
+    }
+
+    match step.data {
+        StepData::Fork => {
+            for fork in &step.next {
+                verify_recurse(fork, input, source, registry, limits, results)?;
+            }
+            Ok(())
+        }
+        StepData::Op(ref op) => {
+            let output = op.execute(input);
+            if output.len() > limits.max_result_length {
+                return Err(Error::BadLength { min: 0, max: limits.max_result_length, val: output.len() });
+            }
+            verify_recurse(&step.next[0], &output, source, registry, limits, results)
+        }
+        StepData::Attestation(Attestation::Bitcoin { height }) => {
+            if input.len() != 32 {
+                return Err(Error::BadLength { min: 32, max: 32, val: input.len() });
+            }
+            let header = source.block_header(height)?;
+            if header.merkle_root[..] != *input {
+                return Err(Error::MerkleRootMismatch(height));
+            }
+            debug!("Bitcoin attestation at height {} verified", height);
+            results.push(VerificationResult {
+                height,
+                block_hash: header.block_hash(),
+                time: header.time
+            });
+            Ok(())
+        }
+        StepData::Attestation(Attestation::Unknown { ref tag, ref data }) => {
+            if let Some(entry) = registry.entry(tag) {
+                entry.verifier.parse(data)?;
+                let result = entry.verifier.verify(input, data)?;
+                debug!("attestation type {} verified", Hexed(tag));
+                results.push(result);
+            }
+            Ok(())
+        }
+        StepData::Attestation(_) => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use attestation::Attestation;
+    use ser::DetachedTimestampFile;
+    use timestamp::{Step, StepData};
+    use tests::{LARGE_TEST, SMALL_TEST};
+    use super::*;
+
+    const GENESIS_HEADER: &[u8] = b"\
+\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+\x00\x00\x00\x00\x00\x00\x3b\xa3\xed\xfd\x7a\x7b\x12\xb2\x7a\xc7\x2c\x3e\x67\x76\x8f\x61\x7f\xc8\x1b\xc3\x88\x8a\x51\x32\
+\x3a\x9f\xb8\xaa\x4b\x1e\x5e\x4a\x29\xab\x5f\x49\xff\xff\x00\x1d\x1d\xac\x2b\x7c";
+
+    struct MapSource(HashMap<usize, BlockHeader>);
+
+    impl BlockHeaderSource for MapSource {
+        fn block_header(&self, height: usize) -> Result<BlockHeader, Error> {
+            self.0.get(&height).cloned().ok_or(Error::NoHeader(height))
+        }
+    }
+
+    /// Builds a source whose headers commit to whatever the timestamp attests
+    fn source_for(timestamp: &Timestamp) -> MapSource {
+        let mut source = MapSource(HashMap::new());
+        for (commitment, attest) in timestamp.attestations() {
+            if let Attestation::Bitcoin { height } = *attest {
+                let mut header = BlockHeader::from_bytes(GENESIS_HEADER).unwrap();
+                header.merkle_root.copy_from_slice(commitment);
+                source.0.insert(height, header);
+            }
+        }
+        source
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = BlockHeader::from_bytes(GENESIS_HEADER).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.time, 1231006505);
+        assert_eq!(header.bits, 0x1d00ffff);
+        assert_eq!(header.nonce, 2083236893);
+        assert_eq!(header.to_bytes(), GENESIS_HEADER);
+
+        let mut hash = header.block_hash();
+        hash.reverse();
+        assert_eq!(format!("{}", ::hex::Hexed(&hash)), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+
+        assert!(BlockHeader::from_bytes(&GENESIS_HEADER[1..]).is_err());
+    }
+
+    #[test]
+    fn verify_bitcoin() {
+        let ots = DetachedTimestampFile::from_reader(LARGE_TEST).unwrap();
+        let mut source = source_for(&ots.timestamp);
+        assert_eq!(source.0.len(), 2);
+        source.0.get_mut(&449397).unwrap().time = 1483000000;
+        source.0.get_mut(&449399).unwrap().time = 1482999000;
+
+        let report = verify(&ots.timestamp, &source).unwrap();
+        assert_eq!(report.attestations.len(), 2);
+        assert_eq!(report.earliest().height, 449399);
+        assert_eq!(report.earliest().block_hash, source.0[&449399].block_hash());
+        assert_eq!(report.earliest_time(), 1482999000);
+
+        // Wrong Merkle root
+        let mut bad_source = MapSource(source.0.clone());
+        bad_source.0.get_mut(&449397).unwrap().merkle_root[0] ^= 1;
+        match verify(&ots.timestamp, &bad_source) {
+            Err(Error::MerkleRootMismatch(449397)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Missing header
+        let mut short_source = MapSource(source.0.clone());
+        short_source.0.remove(&449399);
+        match verify(&ots.timestamp, &short_source) {
+            Err(Error::NoHeader(449399)) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Start digest does not match the attested commitments
+        let mut bad_ts = ots.timestamp.clone();
+        bad_ts.start_digest[0] ^= 1;
+        assert!(verify(&bad_ts, &source).is_err());
+
+        // Hand-built proofs with an op missing its successor are rejected
+        let mut bad_ts = ots.timestamp.clone();
+        let mut step = &mut bad_ts.first_step;
+        while let StepData::Fork = step.data {
+            step = &mut step.next[0];
+        }
+        assert!(matches!(step.data, StepData::Op(_)));
+        step.next.clear();
+        match verify(&bad_ts, &source) {
+            Err(Error::MalformedStep) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Op results are bounded by the limits
+        let limits = Limits { max_result_length: 20, ..Default::default() };
+        match verify_with_limits(&ots.timestamp, &source, &AttestationRegistry::new(), &limits) {
+            Err(Error::BadLength { max: 20, .. }) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Only pending attestations
+        let ots = DetachedTimestampFile::from_reader(SMALL_TEST).unwrap();
+        match verify(&ots.timestamp, &source) {
+            Err(Error::NoVerifiableAttestation) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+    }
+
+    /// Accepts attestations whose 32-byte payload is the commitment itself
+    struct EchoVerifier;
+
+    impl AttestationVerifier for EchoVerifier {
+        fn parse(&self, payload: &[u8]) -> Result<(), Error> {
+            if payload.len() != 32 {
+                return Err(Error::BadAttestation("echo payload is not 32 bytes".to_owned()));
+            }
+            Ok(())
+        }
+
+        fn verify(&self, commitment: &[u8], payload: &[u8]) -> Result<VerificationResult, Error> {
+            if commitment != payload {
+                return Err(Error::BadAttestation("echo mismatch".to_owned()));
+            }
+            Ok(VerificationResult { height: 0, block_hash: [0; 32], time: 1234 })
+        }
+    }
+
+    #[test]
+    fn verify_custom() {
+        let tag = b"\x01\x02\x03\x04\x05\x06\x07\x08";
+        let start = vec![0xaa; 32];
+        let mut ts = Timestamp {
+            start_digest: start.clone(),
+            first_step: Step {
+                data: StepData::Attestation(Attestation::Unknown { tag: tag.to_vec(), data: start.clone() }),
+                output: start.clone(),
+                next: vec![]
+            }
+        };
+        let source = MapSource(HashMap::new());
+
+        let mut registry = AttestationRegistry::new();
+        assert!(verify_with_registry(&ts, &source, &registry).is_err());
+        registry.register(tag, "Echo", EchoVerifier);
+        assert_eq!(format!("{}", Attestation::Unknown { tag: tag.to_vec(), data: vec![0xcc] }), "unsupported Echo attestation: cc");
+
+        registry.parse(&ts).unwrap();
+        let report = verify_with_registry(&ts, &source, &registry).unwrap();
+        assert_eq!(report.earliest_time(), 1234);
+        // Plain verify ignores it
+        match verify(&ts, &source) {
+            Err(Error::NoVerifiableAttestation) => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        ts.first_step.data = StepData::Attestation(Attestation::Unknown { tag: tag.to_vec(), data: vec![0xbb; 32] });
+        registry.parse(&ts).unwrap();
+        match verify_with_registry(&ts, &source, &registry) {
+            Err(Error::BadAttestation(ref msg)) if msg == "echo mismatch" => {}
+            x => panic!("unexpected result {:?}", x)
+        }
+
+        // Malformed payloads are caught by the parser, without verifying
+        ts.first_step.data = StepData::Attestation(Attestation::Unknown { tag: tag.to_vec(), data: vec![0xbb; 5] });
+        assert!(matches!(registry.parse(&ts), Err(Error::BadAttestation(_))));
+        assert!(matches!(verify_with_registry(&ts, &source, &registry), Err(Error::BadAttestation(_))));
+    }
+}